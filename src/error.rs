@@ -13,6 +13,10 @@ pub enum RendererError {
     #[error("A gpu allocator error occured: {0}")]
     GpuAllocator(#[from] gpu_allocator::AllocationError),
 
+    #[cfg(feature = "image-loading")]
+    #[error("An image decoding error occured: {0}")]
+    Image(#[from] image::ImageError),
+
     /// Io errors.
     #[error("A io error occured: {0}")]
     Io(#[from] std::io::Error),
@@ -25,7 +29,23 @@ pub enum RendererError {
     #[error("Bad texture ID: {}", .0.id())]
     BadTexture(TextureId),
 
+    /// Texture data does not match the texture.
+    #[error("Bad texture data: {0}")]
+    BadTextureData(String),
+
+    /// Texture format not supported by the renderer or the device.
+    #[error("Texture format {0:?} is not supported")]
+    UnsupportedFormat(vk::Format),
+
+    /// Block compressed texture format whose device feature is not supported.
+    #[error("Texture format {0:?} requires the {1} device feature")]
+    MissingDeviceFeature(vk::Format, &'static str),
+
     /// Allocator error
     #[error("A error occured when using the allocator: {0}")]
     Allocator(String),
+
+    /// Draw data was not uploaded before being drawn.
+    #[error("Draw data must be uploaded with `Renderer::cmd_upload` before being drawn")]
+    MissingUpload,
 }
@@ -27,14 +27,19 @@
 //!
 //! - Vertex/Index buffers
 //!
-//! The renderer creates a vertex buffer and a index buffer that will be updated every time
+//! The renderer creates a buffer holding both vertices and indices that will be updated every time
 //! `Renderer::cmd_draw` is called. If the vertex/index count is more than what the buffers can
-//! actually hold then the buffers are resized (actually destroyed then re-created).
+//! actually hold then the buffers are resized (actually destroyed then re-created) with some headroom.
+//! Buffers that stay mostly unused for a while are shrunk. See `Options::buffer_policy`.
+//!
+//! Buffers live in host visible memory by default. With `Options::device_local_buffers` they are allocated
+//! in device local memory and `Renderer::cmd_upload` must be called before the render pass. It copies the
+//! draw data from staging buffers, or writes it directly when device local memory is host visible (resizable BAR).
 //!
 //! - Frames in flight
 //!
 //! The renderer support having multiple frames in flight. You need to specify the number of frames
-//! during initialization of the renderer. The renderer manages one vertex and index buffer per frame.
+//! during initialization of the renderer. The renderer manages one vertex/index buffer per frame.
 //!
 //! - No draw call execution
 //!
@@ -42,23 +47,40 @@
 //!
 //! - Custom textures
 //!
-//! The renderer supports custom textures. See `Renderer::textures` for details.
+//! The renderer supports custom textures. It can create them from rgba data with `Renderer::create_texture`,
+//! draw images owned by the application with `Renderer::register_image` or use descriptor sets created by the
+//! application. See `Renderer::textures` for details.
 //!
 //! - Custom Vulkan allocators
 //!
-//! Custom Vulkan allocators are not supported for the moment.
+//! All buffers and images are allocated through the `Allocate` trait. The crate provides `DefaultAllocator`
+//! and, behind their respective features, `GpuAllocator` and `VkMemAllocator`. You can also implement `Allocate`
+//! for your own allocator and create the renderer with `Renderer::with_allocator`.
 //!
 //! ## Features
 //!
 //! ### gpu-allocator
 //!
-//! This feature adds support for gpu-allocator. It changes `Renderer::new` which now takes
-//! a `Arc<Mutex<gpu_allocator::vulkan::Allocator>>`. All internal allocator are then done using the allocator.
+//! This feature adds support for [gpu-allocator][gpu-allocator]. It adds `GpuAllocator` and `Renderer::with_gpu_allocator`
+//! which takes a `Arc<Mutex<gpu_allocator::vulkan::Allocator>>`. All internal allocator are then done using the allocator.
 //!
 //! ### vk-mem
 //!
-//! This feature adds support for [vk-mem-rs][vk-mem-rs]. It adds `Renderer::with_vk_mem_allocator` which takes
-//! a `Arc<Mutex<vk_mem::Allocator>>`. All internal allocator are then done using the allocator.
+//! This feature adds support for [vk-mem-rs][vk-mem-rs]. It adds `VkMemAllocator` and `Renderer::with_vk_mem_allocator`
+//! which takes a `Arc<Mutex<vk_mem::Allocator>>`. All internal allocator are then done using the allocator.
+//!
+//! ### leak-tracker
+//!
+//! This feature adds `LeakTracker`, an allocator wrapping another `Allocate` implementation to track every live
+//! buffer and image along with its size and creation call site. Create the renderer with
+//! `Renderer::with_allocator(LeakTracker::new(allocator), ...)` and list live resources with `Renderer::live_allocations`.
+//! Resources still alive when the renderer is dropped are logged as errors.
+//!
+//! ### image-loading
+//!
+//! This feature adds `Renderer::load_texture_from_file` and `Renderer::load_texture_from_memory` which create renderer
+//! managed textures from image files. PNG and JPEG images are decoded with [image][image] into rgba data, KTX2 and DDS
+//! files are uploaded with their format and mip levels.
 //!
 //! ### dynamic-rendering
 //!
@@ -70,7 +92,7 @@
 //!
 //! You can find an example of integration in the [common module](examples/common/mod.rs) of the examples.
 //!
-//! ```rust
+//! ```ignore
 //! // Example with default allocator
 //! use imgui_rs_vulkan_renderer::Renderer;
 //! let renderer = Renderer::with_default_allocator(
@@ -135,6 +157,7 @@
 //! [imgui-rs]: https://github.com/Gekkio/imgui-rs
 //! [ash]: https://github.com/MaikKlein/ash
 //! [gpu-allocator]: https://github.com/Traverse-Research/gpu-allocator
+//! [image]: https://github.com/image-rs/image
 //! [example]: https://github.com/adrien-ben/imgui-rs-vulkan-renderer/blob/master/examples/common/mod.rs
 
 mod error;
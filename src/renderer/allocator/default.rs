@@ -1,18 +1,62 @@
 use crate::{RendererError, RendererResult};
-use ash::{vk, Device};
+use ash::{vk, Device, Instance};
 
-use super::Allocate;
+use super::{Allocate, MappedPtr, MemoryLocation};
 
-/// Abstraction over memory used by Vulkan resources.
-pub type Memory = vk::DeviceMemory;
+/// Preferred size of the memory blocks resources are sub-allocated from.
+///
+/// Resources larger than this get a block of their own.
+const PREFERRED_BLOCK_SIZE: vk::DeviceSize = 16 * 1024 * 1024;
 
-pub struct Allocator {
+/// Memory sub-allocated by [`DefaultAllocator`].
+#[derive(Debug)]
+pub struct DefaultAllocation {
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    mapped_ptr: Option<MappedPtr>,
+    coherent: bool,
+}
+
+/// Allocator sub-allocating resources from large blocks of device memory.
+///
+/// One set of blocks is created per memory type. Buffers and images never share a block
+/// so `bufferImageGranularity` never has to be taken into account between neighbouring resources.
+/// Memory is returned to its block when a resource is destroyed. One empty block per memory type
+/// is kept for the next allocations and other blocks are freed as soon as they are empty, so
+/// a resource alone in its block can be recreated without allocating device memory again. Host
+/// visible blocks are mapped once when they are allocated.
+///
+/// Buffers are allocated in host coherent memory when available. Otherwise they fall back to
+/// non coherent memory which is flushed after each call to `update_buffer`.
+pub struct DefaultAllocator {
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    non_coherent_atom_size: vk::DeviceSize,
+    blocks: Vec<MemoryBlock>,
+    allocation_callbacks: Option<vk::AllocationCallbacks<'static>>,
 }
 
-impl Allocator {
-    pub fn new(memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
-        Self { memory_properties }
+impl DefaultAllocator {
+    pub fn new(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+
+        Self {
+            memory_properties,
+            non_coherent_atom_size: limits.non_coherent_atom_size.max(1),
+            blocks: Vec::new(),
+            allocation_callbacks: None,
+        }
+    }
+
+    /// Use `allocation_callbacks` when creating and destroying buffers, images and device memory.
+    pub fn with_allocation_callbacks(
+        mut self,
+        allocation_callbacks: vk::AllocationCallbacks<'static>,
+    ) -> Self {
+        self.allocation_callbacks = Some(allocation_callbacks);
+        self
     }
 
     fn find_memory_type(
@@ -33,79 +77,262 @@ impl Allocator {
             "Failed to find suitable memory type.".into(),
         ))
     }
+
+    fn allocate(
+        &mut self,
+        device: &Device,
+        requirements: vk::MemoryRequirements,
+        memory_type_index: u32,
+        linear: bool,
+    ) -> RendererResult<DefaultAllocation> {
+        let allocation = self
+            .blocks
+            .iter_mut()
+            .filter(|b| b.memory_type_index == memory_type_index && b.linear == linear)
+            .find_map(|b| b.allocate(requirements.size, requirements.alignment));
+        if let Some(allocation) = allocation {
+            return Ok(allocation);
+        }
+
+        let heap_index = self.memory_properties.memory_types[memory_type_index as usize].heap_index;
+        let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+        let block_size = PREFERRED_BLOCK_SIZE
+            .min(heap_size / 8)
+            .max(requirements.size);
+
+        log::debug!(
+            "Allocating memory block of {block_size} bytes for memory type {memory_type_index}"
+        );
+        let alloc_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(block_size)
+            .memory_type_index(memory_type_index);
+        let memory =
+            unsafe { device.allocate_memory(&alloc_info, self.allocation_callbacks.as_ref())? };
+
+        let property_flags =
+            self.memory_properties.memory_types[memory_type_index as usize].property_flags;
+        let coherent = property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+        let mapped_ptr = if property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            let ptr = unsafe {
+                device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())?
+            };
+            MappedPtr::new(ptr)
+        } else {
+            None
+        };
+
+        let mut block = MemoryBlock::new(
+            memory,
+            block_size,
+            memory_type_index,
+            linear,
+            mapped_ptr,
+            coherent,
+        );
+        let allocation = block
+            .allocate(requirements.size, requirements.alignment)
+            .ok_or_else(|| RendererError::Allocator("Failed to sub-allocate memory.".into()))?;
+        self.blocks.push(block);
+
+        Ok(allocation)
+    }
+
+    fn free(&mut self, device: &Device, allocation: DefaultAllocation) -> RendererResult<()> {
+        let index = self
+            .blocks
+            .iter()
+            .position(|b| b.memory == allocation.memory)
+            .ok_or_else(|| {
+                RendererError::Allocator("Memory does not belong to this allocator.".into())
+            })?;
+
+        let block = &mut self.blocks[index];
+        block.free(allocation.offset, allocation.size);
+        if !block.is_empty() {
+            return Ok(());
+        }
+
+        // Keep the block unless another empty block can serve the same allocations.
+        let (memory_type_index, linear, size) = (block.memory_type_index, block.linear, block.size);
+        let has_spare_block = self.blocks.iter().enumerate().any(|(i, b)| {
+            i != index
+                && b.memory_type_index == memory_type_index
+                && b.linear == linear
+                && b.is_empty()
+        });
+        if has_spare_block || size > PREFERRED_BLOCK_SIZE {
+            let block = self.blocks.swap_remove(index);
+            self.free_block(device, block);
+        }
+
+        Ok(())
+    }
+
+    fn free_block(&self, device: &Device, block: MemoryBlock) {
+        log::debug!(
+            "Freeing memory block of {} bytes for memory type {}",
+            block.size,
+            block.memory_type_index
+        );
+        unsafe {
+            if block.mapped_ptr.is_some() {
+                device.unmap_memory(block.memory);
+            }
+            device.free_memory(block.memory, self.allocation_callbacks.as_ref());
+        }
+    }
+
+    /// Allocate and bind the memory of `buffer`. The memory is freed if it cannot be bound.
+    fn allocate_buffer_memory(
+        &mut self,
+        device: &Device,
+        buffer: vk::Buffer,
+        location: MemoryLocation,
+    ) -> RendererResult<DefaultAllocation> {
+        let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let required_properties = match location {
+            MemoryLocation::CpuToGpu => vk::MemoryPropertyFlags::HOST_VISIBLE,
+            MemoryLocation::GpuOnly => vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            MemoryLocation::GpuMapped => {
+                vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE
+            }
+        };
+        let mem_type = if location == MemoryLocation::GpuOnly {
+            self.find_memory_type(mem_requirements, required_properties)?
+        } else {
+            self.find_memory_type(
+                mem_requirements,
+                required_properties | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .or_else(|_| self.find_memory_type(mem_requirements, required_properties))?
+        };
+
+        let allocation = self.allocate(device, mem_requirements, mem_type, true)?;
+        if let Err(error) =
+            unsafe { device.bind_buffer_memory(buffer, allocation.memory, allocation.offset) }
+        {
+            self.free(device, allocation)?;
+            return Err(error.into());
+        }
+
+        Ok(allocation)
+    }
+
+    /// Allocate and bind the memory of `image`. The memory is freed if it cannot be bound.
+    fn allocate_image_memory(
+        &mut self,
+        device: &Device,
+        image: vk::Image,
+    ) -> RendererResult<DefaultAllocation> {
+        let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
+        let mem_type_index =
+            self.find_memory_type(mem_requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+        let allocation = self.allocate(device, mem_requirements, mem_type_index, false)?;
+        if let Err(error) =
+            unsafe { device.bind_image_memory(image, allocation.memory, allocation.offset) }
+        {
+            self.free(device, allocation)?;
+            return Err(error.into());
+        }
+
+        Ok(allocation)
+    }
+
+    /// Flush `size` bytes from `offset` of non coherent mapped memory.
+    ///
+    /// The flushed range is extended to `nonCoherentAtomSize` boundaries.
+    fn flush(
+        &self,
+        device: &Device,
+        allocation: &DefaultAllocation,
+        offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    ) -> RendererResult<()> {
+        if allocation.coherent {
+            return Ok(());
+        }
+
+        let block_size = self
+            .blocks
+            .iter()
+            .find(|b| b.memory == allocation.memory)
+            .map(|b| b.size)
+            .ok_or_else(|| {
+                RendererError::Allocator("Memory does not belong to this allocator.".into())
+            })?;
+
+        let atom_size = self.non_coherent_atom_size;
+        let offset = allocation.offset + offset;
+        let start = offset / atom_size * atom_size;
+        let end = (offset + size).next_multiple_of(atom_size).min(block_size);
+
+        let range = vk::MappedMemoryRange::default()
+            .memory(allocation.memory)
+            .offset(start)
+            .size(end - start);
+        unsafe { device.flush_mapped_memory_ranges(&[range])? };
+
+        Ok(())
+    }
 }
 
-impl Allocate for Allocator {
-    type Memory = Memory;
+impl Allocate for DefaultAllocator {
+    type Memory = DefaultAllocation;
 
     fn create_buffer(
         &mut self,
         device: &Device,
         size: usize,
         usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
     ) -> RendererResult<(vk::Buffer, Self::Memory)> {
         let buffer_info = vk::BufferCreateInfo::default()
             .size(size as _)
             .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-        let buffer = unsafe { device.create_buffer(&buffer_info, None)? };
+        let buffer =
+            unsafe { device.create_buffer(&buffer_info, self.allocation_callbacks.as_ref())? };
 
-        let mem_requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
-        let mem_type = self.find_memory_type(
-            mem_requirements,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )?;
+        let allocation = match self.allocate_buffer_memory(device, buffer, location) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe { device.destroy_buffer(buffer, self.allocation_callbacks.as_ref()) };
+                return Err(error);
+            }
+        };
 
-        let alloc_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(mem_requirements.size)
-            .memory_type_index(mem_type);
-        let memory = unsafe { device.allocate_memory(&alloc_info, None)? };
-        unsafe { device.bind_buffer_memory(buffer, memory, 0)? };
+        Ok((buffer, allocation))
+    }
 
-        Ok((buffer, memory))
+    fn supports_host_visible_device_memory(&self) -> bool {
+        let required_properties =
+            vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE;
+        self.memory_properties.memory_types[..self.memory_properties.memory_type_count as usize]
+            .iter()
+            .any(|t| t.property_flags.contains(required_properties))
+    }
+
+    fn memory_size(&self, memory: &Self::Memory) -> vk::DeviceSize {
+        memory.size
     }
 
     fn create_image(
         &mut self,
         device: &Device,
-        width: u32,
-        height: u32,
+        image_info: &vk::ImageCreateInfo,
     ) -> RendererResult<(vk::Image, Self::Memory)> {
-        let extent = vk::Extent3D {
-            width,
-            height,
-            depth: 1,
-        };
-
-        let image_info = vk::ImageCreateInfo::default()
-            .image_type(vk::ImageType::TYPE_2D)
-            .extent(extent)
-            .mip_levels(1)
-            .array_layers(1)
-            .format(vk::Format::R8G8B8A8_UNORM)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .flags(vk::ImageCreateFlags::empty());
-
-        let image = unsafe { device.create_image(&image_info, None)? };
-        let mem_requirements = unsafe { device.get_image_memory_requirements(image) };
-        let mem_type_index =
-            self.find_memory_type(mem_requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
-
-        let alloc_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(mem_requirements.size)
-            .memory_type_index(mem_type_index);
-        let memory = unsafe {
-            let mem = device.allocate_memory(&alloc_info, None)?;
-            device.bind_image_memory(image, mem, 0)?;
-            mem
+        let image = unsafe { device.create_image(image_info, self.allocation_callbacks.as_ref())? };
+        let allocation = match self.allocate_image_memory(device, image) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe { device.destroy_image(image, self.allocation_callbacks.as_ref()) };
+                return Err(error);
+            }
         };
 
-        Ok((image, memory))
+        Ok((image, allocation))
     }
 
     fn destroy_buffer(
@@ -114,12 +341,8 @@ impl Allocate for Allocator {
         buffer: vk::Buffer,
         memory: Self::Memory,
     ) -> RendererResult<()> {
-        unsafe {
-            device.destroy_buffer(buffer, None);
-            device.free_memory(memory, None);
-        }
-
-        Ok(())
+        unsafe { device.destroy_buffer(buffer, self.allocation_callbacks.as_ref()) };
+        self.free(device, memory)
     }
 
     fn destroy_image(
@@ -128,28 +351,174 @@ impl Allocate for Allocator {
         image: vk::Image,
         memory: Self::Memory,
     ) -> RendererResult<()> {
-        unsafe {
-            device.destroy_image(image, None);
-            device.free_memory(memory, None);
-        }
-
-        Ok(())
+        unsafe { device.destroy_image(image, self.allocation_callbacks.as_ref()) };
+        self.free(device, memory)
     }
 
-    fn update_buffer<T: Copy>(
+    fn update_buffer<'a, T: Copy + 'a>(
         &mut self,
         device: &Device,
         memory: &mut Self::Memory,
-        data: &[T],
+        offset: usize,
+        data: impl IntoIterator<Item = &'a [T]>,
     ) -> RendererResult<()> {
-        let size = std::mem::size_of_val(data) as _;
-        unsafe {
-            let data_ptr = device.map_memory(*memory, 0, size, vk::MemoryMapFlags::empty())?;
-            let mut align = ash::util::Align::new(data_ptr, std::mem::align_of::<T>() as _, size);
-            align.copy_from_slice(data);
-            device.unmap_memory(*memory);
-        }
+        let mapped_ptr = memory
+            .mapped_ptr
+            .ok_or_else(|| RendererError::Allocator("Buffer memory is not mapped.".into()))?;
+        let size = unsafe { mapped_ptr.write(memory.size, offset as _, data)? };
 
+        self.flush(device, memory, offset as _, size)
+    }
+
+    fn free_unused_memory(&mut self, device: &Device) -> RendererResult<()> {
+        let (empty, used) = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .partition(MemoryBlock::is_empty);
+        self.blocks = used;
+        for block in empty {
+            self.free_block(device, block);
+        }
         Ok(())
     }
 }
+
+/// A block of device memory and its free ranges.
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    memory_type_index: u32,
+    linear: bool,
+    mapped_ptr: Option<MappedPtr>,
+    coherent: bool,
+    /// Free ranges as (offset, size), sorted by offset and never adjacent.
+    free_ranges: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+}
+
+impl MemoryBlock {
+    fn new(
+        memory: vk::DeviceMemory,
+        size: vk::DeviceSize,
+        memory_type_index: u32,
+        linear: bool,
+        mapped_ptr: Option<MappedPtr>,
+        coherent: bool,
+    ) -> Self {
+        Self {
+            memory,
+            size,
+            memory_type_index,
+            linear,
+            mapped_ptr,
+            coherent,
+            free_ranges: vec![(0, size)],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.free_ranges == [(0, self.size)]
+    }
+
+    /// First fit allocation in the free list.
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<DefaultAllocation> {
+        let (index, offset) = self.free_ranges.iter().enumerate().find_map(
+            |(index, &(range_offset, range_size))| {
+                let offset = range_offset.next_multiple_of(alignment);
+                (offset + size <= range_offset + range_size).then_some((index, offset))
+            },
+        )?;
+
+        let (range_offset, range_size) = self.free_ranges.remove(index);
+        let range_end = range_offset + range_size;
+        let end = offset + size;
+        if end < range_end {
+            self.free_ranges.insert(index, (end, range_end - end));
+        }
+        if offset > range_offset {
+            self.free_ranges
+                .insert(index, (range_offset, offset - range_offset));
+        }
+
+        Some(DefaultAllocation {
+            memory: self.memory,
+            offset,
+            size,
+            mapped_ptr: self.mapped_ptr.map(|ptr| ptr.add(offset)),
+            coherent: self.coherent,
+        })
+    }
+
+    /// Return a range to the free list, merging it with its neighbours.
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let index = self.free_ranges.partition_point(|&(o, _)| o < offset);
+        self.free_ranges.insert(index, (offset, size));
+
+        if index + 1 < self.free_ranges.len() {
+            let (next_offset, next_size) = self.free_ranges[index + 1];
+            if offset + size == next_offset {
+                self.free_ranges[index].1 += next_size;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+        if index > 0 {
+            let (prev_offset, prev_size) = self.free_ranges[index - 1];
+            if prev_offset + prev_size == offset {
+                self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                self.free_ranges.remove(index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: vk::DeviceSize) -> MemoryBlock {
+        MemoryBlock::new(vk::DeviceMemory::null(), size, 0, true, None, true)
+    }
+
+    #[test]
+    fn allocate_pads_to_alignment() {
+        let mut block = block(1024);
+
+        assert_eq!(block.allocate(10, 1).unwrap().offset, 0);
+        let aligned = block.allocate(16, 256).unwrap();
+        assert_eq!((aligned.offset, aligned.size), (256, 16));
+        assert_eq!(block.free_ranges, [(10, 246), (272, 752)]);
+
+        // The padding is used by smaller allocations.
+        assert_eq!(block.allocate(8, 8).unwrap().offset, 16);
+        assert!(block.allocate(1024, 1).is_none());
+    }
+
+    #[test]
+    fn allocate_reuses_first_fit() {
+        let mut block = block(1024);
+        let offsets = [100, 100, 100].map(|size| block.allocate(size, 1).unwrap().offset);
+        assert_eq!(offsets, [0, 100, 200]);
+
+        block.free(100, 100);
+        assert_eq!(block.allocate(50, 1).unwrap().offset, 100);
+        assert_eq!(block.allocate(50, 1).unwrap().offset, 150);
+        assert_eq!(block.allocate(50, 1).unwrap().offset, 300);
+    }
+
+    #[test]
+    fn free_merges_with_both_neighbours() {
+        let mut block = block(1024);
+        for _ in 0..3 {
+            block.allocate(100, 1).unwrap();
+        }
+
+        block.free(0, 100);
+        block.free(200, 100);
+        assert_eq!(block.free_ranges, [(0, 100), (200, 824)]);
+        block.free(100, 100);
+        assert_eq!(block.free_ranges, [(0, 1024)]);
+        assert!(block.is_empty());
+    }
+}
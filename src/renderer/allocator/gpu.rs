@@ -0,0 +1,194 @@
+use super::{Allocate, MappedPtr, MemoryLocation};
+use crate::{RendererError, RendererResult};
+use ash::{vk, Device};
+use gpu_allocator::vulkan::{self, Allocation, AllocationCreateDesc, AllocationScheme};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Allocator backed by a shared gpu-allocator allocator.
+pub struct GpuAllocator {
+    pub allocator: Arc<Mutex<vulkan::Allocator>>,
+    allocation_callbacks: Option<vk::AllocationCallbacks<'static>>,
+}
+
+impl GpuAllocator {
+    pub fn new(allocator: Arc<Mutex<vulkan::Allocator>>) -> Self {
+        Self {
+            allocator,
+            allocation_callbacks: None,
+        }
+    }
+
+    /// Use `allocation_callbacks` when creating and destroying buffers and images.
+    ///
+    /// Device memory is allocated by gpu-allocator which does not use allocation callbacks.
+    pub fn with_allocation_callbacks(
+        mut self,
+        allocation_callbacks: vk::AllocationCallbacks<'static>,
+    ) -> Self {
+        self.allocation_callbacks = Some(allocation_callbacks);
+        self
+    }
+
+    fn get_allocator(&self) -> RendererResult<MutexGuard<'_, vulkan::Allocator>> {
+        self.allocator.lock().map_err(|e| {
+            RendererError::Allocator(format!("Failed to acquire lock on allocator: {e}"))
+        })
+    }
+
+    /// Allocate and bind the memory of `buffer`. The memory is freed if it cannot be bound.
+    fn allocate_buffer_memory(
+        &self,
+        device: &Device,
+        buffer: vk::Buffer,
+        location: gpu_allocator::MemoryLocation,
+    ) -> RendererResult<Allocation> {
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+
+        let allocation = self.get_allocator()?.allocate(&AllocationCreateDesc {
+            name: "imgui-rs-vulkan-renderer-buffer",
+            requirements,
+            location,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+
+        if let Err(error) =
+            unsafe { device.bind_buffer_memory(buffer, allocation.memory(), allocation.offset()) }
+        {
+            self.get_allocator()?.free(allocation)?;
+            return Err(error.into());
+        }
+
+        Ok(allocation)
+    }
+
+    /// Allocate and bind the memory of `image`. The memory is freed if it cannot be bound.
+    fn allocate_image_memory(
+        &self,
+        device: &Device,
+        image: vk::Image,
+    ) -> RendererResult<Allocation> {
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let allocation = self.get_allocator()?.allocate(&AllocationCreateDesc {
+            name: "imgui-rs-vulkan-renderer-image",
+            requirements,
+            location: gpu_allocator::MemoryLocation::GpuOnly,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        })?;
+
+        if let Err(error) =
+            unsafe { device.bind_image_memory(image, allocation.memory(), allocation.offset()) }
+        {
+            self.get_allocator()?.free(allocation)?;
+            return Err(error.into());
+        }
+
+        Ok(allocation)
+    }
+}
+
+impl Allocate for GpuAllocator {
+    type Memory = Allocation;
+
+    fn create_buffer(
+        &mut self,
+        device: &Device,
+        size: usize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> RendererResult<(vk::Buffer, Self::Memory)> {
+        let buffer_info = vk::BufferCreateInfo::default()
+            .size(size as _)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let location = match location {
+            MemoryLocation::CpuToGpu => gpu_allocator::MemoryLocation::CpuToGpu,
+            MemoryLocation::GpuOnly => gpu_allocator::MemoryLocation::GpuOnly,
+            MemoryLocation::GpuMapped => {
+                return Err(RendererError::Allocator(
+                    "Host visible device memory is not supported.".into(),
+                ))
+            }
+        };
+
+        let buffer =
+            unsafe { device.create_buffer(&buffer_info, self.allocation_callbacks.as_ref())? };
+        let allocation = match self.allocate_buffer_memory(device, buffer, location) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe { device.destroy_buffer(buffer, self.allocation_callbacks.as_ref()) };
+                return Err(error);
+            }
+        };
+
+        Ok((buffer, allocation))
+    }
+
+    fn memory_size(&self, memory: &Self::Memory) -> vk::DeviceSize {
+        memory.size()
+    }
+
+    fn create_image(
+        &mut self,
+        device: &Device,
+        image_info: &vk::ImageCreateInfo,
+    ) -> RendererResult<(vk::Image, Self::Memory)> {
+        let image = unsafe { device.create_image(image_info, self.allocation_callbacks.as_ref())? };
+        let allocation = match self.allocate_image_memory(device, image) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe { device.destroy_image(image, self.allocation_callbacks.as_ref()) };
+                return Err(error);
+            }
+        };
+
+        Ok((image, allocation))
+    }
+
+    fn destroy_buffer(
+        &mut self,
+        device: &Device,
+        buffer: vk::Buffer,
+        memory: Self::Memory,
+    ) -> RendererResult<()> {
+        let mut allocator = self.get_allocator()?;
+
+        allocator.free(memory)?;
+        unsafe { device.destroy_buffer(buffer, self.allocation_callbacks.as_ref()) };
+
+        Ok(())
+    }
+
+    fn destroy_image(
+        &mut self,
+        device: &Device,
+        image: vk::Image,
+        memory: Self::Memory,
+    ) -> RendererResult<()> {
+        let mut allocator = self.get_allocator()?;
+
+        allocator.free(memory)?;
+        unsafe { device.destroy_image(image, self.allocation_callbacks.as_ref()) };
+
+        Ok(())
+    }
+
+    fn update_buffer<'a, T: Copy + 'a>(
+        &mut self,
+        _device: &Device,
+        memory: &mut Self::Memory,
+        offset: usize,
+        data: impl IntoIterator<Item = &'a [T]>,
+    ) -> RendererResult<()> {
+        let mapped_ptr = memory
+            .mapped_ptr()
+            .and_then(|ptr| MappedPtr::new(ptr.as_ptr()))
+            .ok_or_else(|| RendererError::Allocator("Buffer memory is not mapped.".into()))?;
+        unsafe { mapped_ptr.write(memory.size(), offset as _, data)? };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+//! Allocator recording buffers in host memory, for tests that do not need a device.
+
+use super::{Allocate, MemoryLocation};
+use crate::{RendererError, RendererResult};
+use ash::{vk, vk::Handle, Device};
+
+/// Return a device whose commands all panic. Only usable with [`MockAllocator`].
+pub(crate) fn device() -> Device {
+    unsafe { Device::load_with(|_| std::ptr::null(), vk::Device::null()) }
+}
+
+pub(crate) struct MockMemory {
+    pub data: Vec<u8>,
+}
+
+/// Allocator keeping the content of buffers in vectors. Images are not supported.
+#[derive(Default)]
+pub(crate) struct MockAllocator {
+    next_handle: u64,
+    /// Number of buffers created and not destroyed yet.
+    pub live_buffers: usize,
+    /// Number of buffers created since the allocator was created.
+    pub created_buffers: usize,
+}
+
+impl Allocate for MockAllocator {
+    type Memory = MockMemory;
+
+    fn create_buffer(
+        &mut self,
+        _device: &Device,
+        size: usize,
+        _usage: vk::BufferUsageFlags,
+        _location: MemoryLocation,
+    ) -> RendererResult<(vk::Buffer, Self::Memory)> {
+        self.next_handle += 1;
+        self.live_buffers += 1;
+        self.created_buffers += 1;
+        let data = vec![0; size];
+        Ok((vk::Buffer::from_raw(self.next_handle), MockMemory { data }))
+    }
+
+    fn memory_size(&self, memory: &Self::Memory) -> vk::DeviceSize {
+        memory.data.len() as _
+    }
+
+    fn create_image(
+        &mut self,
+        _device: &Device,
+        _image_info: &vk::ImageCreateInfo,
+    ) -> RendererResult<(vk::Image, Self::Memory)> {
+        Err(RendererError::Allocator(
+            "MockAllocator does not create images".into(),
+        ))
+    }
+
+    fn destroy_buffer(
+        &mut self,
+        _device: &Device,
+        _buffer: vk::Buffer,
+        _memory: Self::Memory,
+    ) -> RendererResult<()> {
+        self.live_buffers -= 1;
+        Ok(())
+    }
+
+    fn destroy_image(
+        &mut self,
+        _device: &Device,
+        _image: vk::Image,
+        _memory: Self::Memory,
+    ) -> RendererResult<()> {
+        Ok(())
+    }
+
+    fn update_buffer<'a, T: Copy + 'a>(
+        &mut self,
+        _device: &Device,
+        memory: &mut Self::Memory,
+        offset: usize,
+        data: impl IntoIterator<Item = &'a [T]>,
+    ) -> RendererResult<()> {
+        let mut offset = offset;
+        for slice in data {
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    slice.as_ptr().cast::<u8>(),
+                    std::mem::size_of_val(slice),
+                )
+            };
+            memory.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            offset += bytes.len();
+        }
+        Ok(())
+    }
+}
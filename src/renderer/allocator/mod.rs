@@ -1,51 +1,101 @@
-#[cfg(not(any(feature = "gpu-allocator", feature = "vk-mem")))]
+//! Memory allocators used by the renderer.
+//!
+//! The renderer allocates its buffers and images through the [`Allocate`] trait. The crate
+//! provides a [`DefaultAllocator`] and, behind their respective features, allocators based on
+//! gpu-allocator and vk-mem. You can also implement [`Allocate`] for your own allocator and pass
+//! it to [`Renderer::with_allocator`](crate::Renderer::with_allocator).
+
 mod default;
 
-#[cfg(not(any(feature = "gpu-allocator", feature = "vk-mem")))]
-pub use self::default::{Allocator, Memory};
+pub use self::default::{DefaultAllocation, DefaultAllocator};
+
+#[cfg(feature = "gpu-allocator")]
+mod gpu;
 
+#[cfg(feature = "gpu-allocator")]
+pub use self::gpu::GpuAllocator;
 
 #[cfg(feature = "vk-mem")]
 mod vkmem;
 
 #[cfg(feature = "vk-mem")]
-pub use self::vkmem::{Allocator, Memory};
+pub use self::vkmem::{VkMemAllocation, VkMemAllocator};
+
+#[cfg(feature = "leak-tracker")]
+mod tracker;
+
+#[cfg(feature = "leak-tracker")]
+pub use self::tracker::{LeakTracker, LiveAllocation, ResourceKind, TrackedMemory};
 
-use crate::RendererResult;
+#[cfg(test)]
+pub(crate) mod mock;
+
+use crate::{RendererError, RendererResult};
 use ash::{vk, Device};
+use std::{ffi::c_void, ptr::NonNull};
+
+/// Memory location of a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLocation {
+    /// Host visible memory.
+    CpuToGpu,
+    /// Device local memory. It is never accessed from the host.
+    GpuOnly,
+    /// Device local and host visible memory, often available with resizable BAR.
+    ///
+    /// Only requested when [`Allocate::supports_host_visible_device_memory`] returns true.
+    GpuMapped,
+}
 
 /// Base allocator trait for all implementations.
 pub trait Allocate {
+    /// Memory bound to the buffers and images created by the allocator.
     type Memory;
 
     /// Create a Vulkan buffer.
     ///
+    /// Unless location is [`MemoryLocation::GpuOnly`], the buffer memory must be host visible.
+    /// Implementations should keep it mapped for the whole lifetime of the buffer so
+    /// [`update_buffer`](Allocate::update_buffer) can write to it directly. It does not have to be
+    /// host coherent.
+    ///
     /// # Arguments
     ///
     /// * `device` - A reference to Vulkan device.
     /// * `size` - The size in bytes of the buffer.
     /// * `usage` - The buffer usage flags.
+    /// * `location` - The memory location of the buffer.
     fn create_buffer(
         &mut self,
         device: &Device,
         size: usize,
         usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
     ) -> RendererResult<(vk::Buffer, Self::Memory)>;
 
+    /// Return true if buffers can be created with [`MemoryLocation::GpuMapped`].
+    fn supports_host_visible_device_memory(&self) -> bool {
+        false
+    }
+
+    /// Return the size in bytes of the memory allocated for a buffer or an image.
+    ///
+    /// Used to report the memory held by the renderer. See [`Renderer::memory_stats`](crate::Renderer::memory_stats).
+    fn memory_size(&self, memory: &Self::Memory) -> vk::DeviceSize;
+
     /// Create a Vulkan image.
     ///
-    /// This creates a 2D RGBA8_UNORM image with TRANSFER_DST and SAMPLED flags.
+    /// This creates an image described by `image_info` and binds it to device local memory.
+    /// The renderer creates 2D images with optimal tiling and at least TRANSFER_DST and SAMPLED usages.
     ///
     /// # Arguments
     ///
     /// * `device` - A reference to Vulkan device.
-    /// * `width` - The width of the image to create.
-    /// * `height` - The height of the image to create.
+    /// * `image_info` - The description of the image to create.
     fn create_image(
         &mut self,
         device: &Device,
-        width: u32,
-        height: u32,
+        image_info: &vk::ImageCreateInfo,
     ) -> RendererResult<(vk::Image, Self::Memory)>;
 
     /// Destroys a buffer.
@@ -78,15 +128,88 @@ pub trait Allocate {
 
     /// Update buffer data
     ///
+    /// Called every frame for vertex and index buffers. The slices of `data` are written one
+    /// after the other from `offset` so draw lists can be copied straight into the buffer.
+    /// If the buffer memory is not host coherent, implementations must flush the written range.
+    ///
     /// # Arguments
     ///
     /// * `device` - A reference to Vulkan device.
     /// * `memory` - The memory of the buffer to update.
+    /// * `offset` - The offset in bytes at which data is written.
     /// * `data` - The data to update the buffer with.
-    fn update_buffer<T: Copy>(
+    fn update_buffer<'a, T: Copy + 'a>(
         &mut self,
         device: &Device,
         memory: &mut Self::Memory,
-        data: &[T],
+        offset: usize,
+        data: impl IntoIterator<Item = &'a [T]>,
     ) -> RendererResult<()>;
+
+    /// Free the memory kept by the allocator for future allocations.
+    ///
+    /// Called when the renderer is dropped, once all its buffers and images are destroyed.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - A reference to Vulkan device.
+    fn free_unused_memory(&mut self, _device: &Device) -> RendererResult<()> {
+        Ok(())
+    }
+}
+
+/// Pointer to persistently mapped memory.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MappedPtr(NonNull<c_void>);
+
+// The pointer is only written through while the owning allocation is exclusively borrowed.
+unsafe impl Send for MappedPtr {}
+unsafe impl Sync for MappedPtr {}
+
+impl MappedPtr {
+    pub(crate) fn new(ptr: *mut c_void) -> Option<Self> {
+        NonNull::new(ptr).map(Self)
+    }
+
+    /// Return a pointer `offset` bytes further in the mapped memory.
+    pub(crate) fn add(self, offset: vk::DeviceSize) -> Self {
+        Self(unsafe { self.0.byte_add(offset as _) })
+    }
+
+    /// Copy the slices of `data` one after the other from `offset` bytes in the mapped memory.
+    ///
+    /// Returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError`] - If data does not fit in `capacity` bytes.
+    ///
+    /// # Safety
+    ///
+    /// The memory must still be mapped and hold at least `capacity` bytes.
+    pub(crate) unsafe fn write<'a, T: Copy + 'a>(
+        self,
+        capacity: vk::DeviceSize,
+        offset: vk::DeviceSize,
+        data: impl IntoIterator<Item = &'a [T]>,
+    ) -> RendererResult<vk::DeviceSize> {
+        let start = offset;
+        let mut offset = offset;
+        for chunk in data {
+            let size = std::mem::size_of_val(chunk) as vk::DeviceSize;
+            if offset + size > capacity {
+                return Err(RendererError::Allocator(format!(
+                    "Cannot write {} bytes to a buffer of {capacity} bytes.",
+                    offset + size
+                )));
+            }
+            std::ptr::copy_nonoverlapping(
+                chunk.as_ptr() as *const u8,
+                self.add(offset).0.as_ptr() as *mut u8,
+                size as _,
+            );
+            offset += size;
+        }
+        Ok(offset - start)
+    }
 }
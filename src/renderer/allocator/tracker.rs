@@ -0,0 +1,243 @@
+use super::{Allocate, MemoryLocation};
+use crate::RendererResult;
+use ash::{vk, Device};
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    collections::BTreeMap,
+    fmt,
+    panic::Location,
+};
+
+/// Kind of resource tracked by [`LeakTracker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Buffer,
+    Image,
+}
+
+/// A buffer or an image created through [`LeakTracker`] and not destroyed yet.
+#[derive(Debug)]
+pub struct LiveAllocation {
+    /// Kind of the resource.
+    pub kind: ResourceKind,
+    /// Size in bytes of the resource memory, as reported by the wrapped allocator.
+    pub size: vk::DeviceSize,
+    /// Location of the call that created the resource.
+    ///
+    /// For resources created by the renderer, such as textures and vertex/index buffers, this is
+    /// the call to the renderer made by the application.
+    pub location: &'static Location<'static>,
+    /// Backtrace of the call that created the resource.
+    ///
+    /// Only captured when backtraces are enabled with `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`.
+    pub backtrace: Backtrace,
+}
+
+impl fmt::Display for LiveAllocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} of {} bytes created at {}",
+            self.kind, self.size, self.location
+        )?;
+        if self.backtrace.status() == BacktraceStatus::Captured {
+            write!(f, "\n{}", self.backtrace)?;
+        }
+        Ok(())
+    }
+}
+
+/// Memory allocated by [`LeakTracker`].
+pub struct TrackedMemory<M> {
+    id: u64,
+    memory: M,
+}
+
+/// Allocator wrapping another allocator to keep track of every live buffer and image.
+///
+/// Each resource is recorded with its size and the location it was created from. Resources
+/// still alive when the tracker is dropped, usually along with the renderer, are logged as errors.
+///
+/// Tracking has a cost on each allocation so it is meant to be used in debug builds.
+pub struct LeakTracker<A: Allocate> {
+    allocator: A,
+    next_id: u64,
+    live_allocations: BTreeMap<u64, LiveAllocation>,
+}
+
+impl<A: Allocate> LeakTracker<A> {
+    pub fn new(allocator: A) -> Self {
+        Self {
+            allocator,
+            next_id: 0,
+            live_allocations: BTreeMap::new(),
+        }
+    }
+
+    /// Return the wrapped allocator.
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    /// Return the buffers and images that are still alive, in creation order.
+    pub fn live_allocations(&self) -> impl Iterator<Item = &LiveAllocation> {
+        self.live_allocations.values()
+    }
+
+    #[track_caller]
+    fn track(&mut self, kind: ResourceKind, memory: A::Memory) -> TrackedMemory<A::Memory> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.live_allocations.insert(
+            id,
+            LiveAllocation {
+                kind,
+                size: self.allocator.memory_size(&memory),
+                location: Location::caller(),
+                backtrace: Backtrace::capture(),
+            },
+        );
+
+        TrackedMemory { id, memory }
+    }
+
+    fn untrack(&mut self, memory: &TrackedMemory<A::Memory>) {
+        if self.live_allocations.remove(&memory.id).is_none() {
+            log::warn!("Destroying a resource that is not tracked by this allocator");
+        }
+    }
+}
+
+impl<A: Allocate> Allocate for LeakTracker<A> {
+    type Memory = TrackedMemory<A::Memory>;
+
+    #[track_caller]
+    fn create_buffer(
+        &mut self,
+        device: &Device,
+        size: usize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> RendererResult<(vk::Buffer, Self::Memory)> {
+        let (buffer, memory) = self
+            .allocator
+            .create_buffer(device, size, usage, location)?;
+        Ok((buffer, self.track(ResourceKind::Buffer, memory)))
+    }
+
+    fn supports_host_visible_device_memory(&self) -> bool {
+        self.allocator.supports_host_visible_device_memory()
+    }
+
+    fn memory_size(&self, memory: &Self::Memory) -> vk::DeviceSize {
+        self.allocator.memory_size(&memory.memory)
+    }
+
+    #[track_caller]
+    fn create_image(
+        &mut self,
+        device: &Device,
+        image_info: &vk::ImageCreateInfo,
+    ) -> RendererResult<(vk::Image, Self::Memory)> {
+        let (image, memory) = self.allocator.create_image(device, image_info)?;
+        Ok((image, self.track(ResourceKind::Image, memory)))
+    }
+
+    fn destroy_buffer(
+        &mut self,
+        device: &Device,
+        buffer: vk::Buffer,
+        memory: Self::Memory,
+    ) -> RendererResult<()> {
+        self.untrack(&memory);
+        self.allocator.destroy_buffer(device, buffer, memory.memory)
+    }
+
+    fn destroy_image(
+        &mut self,
+        device: &Device,
+        image: vk::Image,
+        memory: Self::Memory,
+    ) -> RendererResult<()> {
+        self.untrack(&memory);
+        self.allocator.destroy_image(device, image, memory.memory)
+    }
+
+    fn update_buffer<'a, T: Copy + 'a>(
+        &mut self,
+        device: &Device,
+        memory: &mut Self::Memory,
+        offset: usize,
+        data: impl IntoIterator<Item = &'a [T]>,
+    ) -> RendererResult<()> {
+        self.allocator
+            .update_buffer(device, &mut memory.memory, offset, data)
+    }
+
+    fn free_unused_memory(&mut self, device: &Device) -> RendererResult<()> {
+        self.allocator.free_unused_memory(device)
+    }
+}
+
+impl<A: Allocate> Drop for LeakTracker<A> {
+    fn drop(&mut self) {
+        if self.live_allocations.is_empty() {
+            return;
+        }
+
+        log::error!(
+            "{} buffers and images were not destroyed",
+            self.live_allocations.len()
+        );
+        for allocation in self.live_allocations.values() {
+            log::error!("Leaked {allocation}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{
+        allocator::mock::{device, MockAllocator},
+        staging::StagingPool,
+    };
+
+    #[test]
+    fn location_is_caller() {
+        let device = device();
+        let mut tracker = LeakTracker::new(MockAllocator::default());
+
+        let (buffer, memory) = tracker
+            .create_buffer(
+                &device,
+                16,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                MemoryLocation::CpuToGpu,
+            )
+            .unwrap();
+        let allocation = tracker.live_allocations().next().unwrap();
+        assert_eq!(allocation.location.file(), file!());
+
+        tracker.destroy_buffer(&device, buffer, memory).unwrap();
+        assert_eq!(tracker.live_allocations().count(), 0);
+    }
+
+    #[test]
+    fn location_is_caller_of_renderer_helpers() {
+        let device = device();
+        let mut tracker = LeakTracker::new(MockAllocator::default());
+        let mut staging = StagingPool::new();
+
+        let region = staging.allocate(&device, &mut tracker, &[0; 16]).unwrap();
+        let line = line!() - 1;
+        let allocation = tracker.live_allocations().next().unwrap();
+        assert_eq!(allocation.location.file(), file!());
+        assert_eq!(allocation.location.line(), line);
+
+        staging.free(&device, &mut tracker, region).unwrap();
+        staging.destroy(&device, &mut tracker).unwrap();
+        assert_eq!(tracker.live_allocations().count(), 0);
+    }
+}
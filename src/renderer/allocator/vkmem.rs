@@ -1,84 +1,116 @@
-use super::Allocate;
+use super::{Allocate, MappedPtr, MemoryLocation};
 use crate::{RendererError, RendererResult};
 use ash::{vk, Device};
 use std::sync::{Arc, Mutex, MutexGuard};
-use vk_mem::{
-    Alloc, Allocation, AllocationCreateFlags, AllocationCreateInfo, Allocator as GpuAllocator,
-    MemoryUsage,
-};
-
-/// Abstraction over memory used by Vulkan resources.
-pub type Memory = Allocation;
+use vk_mem::{Alloc, Allocation, AllocationCreateFlags, AllocationCreateInfo, MemoryUsage};
+
+/// Memory allocated by [`VkMemAllocator`].
+pub struct VkMemAllocation {
+    allocation: Allocation,
+    mapped_ptr: Option<MappedPtr>,
+    size: vk::DeviceSize,
+    coherent: bool,
+}
 
-pub struct Allocator {
-    pub allocator: Arc<Mutex<GpuAllocator>>,
+/// Allocator backed by a shared vk-mem allocator.
+///
+/// Buffers are created persistently mapped. Buffers, images and device memory are created
+/// with the allocation callbacks the vk-mem allocator was created with.
+pub struct VkMemAllocator {
+    pub allocator: Arc<Mutex<vk_mem::Allocator>>,
 }
 
-impl Allocator {
+impl VkMemAllocator {
     pub fn new(allocator: Arc<Mutex<vk_mem::Allocator>>) -> Self {
         Self { allocator }
     }
 
-    fn get_allocator(&self) -> RendererResult<MutexGuard<GpuAllocator>> {
+    fn get_allocator(&self) -> RendererResult<MutexGuard<'_, vk_mem::Allocator>> {
         self.allocator.lock().map_err(|e| {
             RendererError::Allocator(format!("Failed to acquire lock on allocator: {e}"))
         })
     }
 }
 
-impl Allocate for Allocator {
-    type Memory = Memory;
+impl Allocate for VkMemAllocator {
+    type Memory = VkMemAllocation;
 
     fn create_buffer(
         &mut self,
         _device: &Device,
         size: usize,
         usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
     ) -> RendererResult<(vk::Buffer, Self::Memory)> {
         let buffer_info = vk::BufferCreateInfo::default()
             .size(size as _)
             .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-        let buffer_alloc_info = AllocationCreateInfo {
-            usage: MemoryUsage::AutoPreferHost,
-            flags: AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
-            ..Default::default()
+        let mapped_flags =
+            AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE | AllocationCreateFlags::MAPPED;
+        let buffer_alloc_info = match location {
+            MemoryLocation::CpuToGpu => AllocationCreateInfo {
+                usage: MemoryUsage::AutoPreferHost,
+                flags: mapped_flags,
+                ..Default::default()
+            },
+            MemoryLocation::GpuOnly => AllocationCreateInfo {
+                usage: MemoryUsage::AutoPreferDevice,
+                ..Default::default()
+            },
+            MemoryLocation::GpuMapped => AllocationCreateInfo {
+                usage: MemoryUsage::AutoPreferDevice,
+                flags: mapped_flags,
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL
+                    | vk::MemoryPropertyFlags::HOST_VISIBLE,
+                ..Default::default()
+            },
         };
 
         let allocator = self.get_allocator()?;
 
         let (buffer, allocation) =
             unsafe { allocator.create_buffer(&buffer_info, &buffer_alloc_info)? };
+        let allocation_info = allocator.get_allocation_info(&allocation);
+        let mapped_ptr = MappedPtr::new(allocation_info.mapped_data);
+        let coherent = unsafe { allocator.get_memory_properties() }.memory_types
+            [allocation_info.memory_type as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+
+        Ok((
+            buffer,
+            VkMemAllocation {
+                allocation,
+                mapped_ptr,
+                size: allocation_info.size,
+                coherent,
+            },
+        ))
+    }
+
+    fn supports_host_visible_device_memory(&self) -> bool {
+        let Ok(allocator) = self.get_allocator() else {
+            return false;
+        };
+        let memory_properties = unsafe { allocator.get_memory_properties() };
+        let required_properties =
+            vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE;
+        memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+            .iter()
+            .any(|t| t.property_flags.contains(required_properties))
+    }
 
-        Ok((buffer, allocation))
+    fn memory_size(&self, memory: &Self::Memory) -> vk::DeviceSize {
+        memory.size
     }
 
     fn create_image(
         &mut self,
         _device: &Device,
-        width: u32,
-        height: u32,
+        image_info: &vk::ImageCreateInfo,
     ) -> RendererResult<(vk::Image, Self::Memory)> {
-        let extent = vk::Extent3D {
-            width,
-            height,
-            depth: 1,
-        };
-
-        let image_info = vk::ImageCreateInfo::default()
-            .image_type(vk::ImageType::TYPE_2D)
-            .extent(extent)
-            .mip_levels(1)
-            .array_layers(1)
-            .format(vk::Format::R8G8B8A8_UNORM)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .flags(vk::ImageCreateFlags::empty());
-
         let image_alloc_info = AllocationCreateInfo {
             usage: MemoryUsage::AutoPreferDevice,
             ..Default::default()
@@ -86,10 +118,18 @@ impl Allocate for Allocator {
 
         let allocator = self.get_allocator()?;
 
-        let (image, allocation) =
-            unsafe { allocator.create_image(&image_info, &image_alloc_info)? };
-
-        Ok((image, allocation))
+        let (image, allocation) = unsafe { allocator.create_image(image_info, &image_alloc_info)? };
+        let size = allocator.get_allocation_info(&allocation).size;
+
+        Ok((
+            image,
+            VkMemAllocation {
+                allocation,
+                mapped_ptr: None,
+                size,
+                coherent: true,
+            },
+        ))
     }
 
     fn destroy_buffer(
@@ -100,7 +140,7 @@ impl Allocate for Allocator {
     ) -> RendererResult<()> {
         let allocator = self.get_allocator()?;
 
-        unsafe { allocator.destroy_buffer(buffer, &mut memory) };
+        unsafe { allocator.destroy_buffer(buffer, &mut memory.allocation) };
 
         Ok(())
     }
@@ -113,25 +153,27 @@ impl Allocate for Allocator {
     ) -> RendererResult<()> {
         let allocator = self.get_allocator()?;
 
-        unsafe { allocator.destroy_image(image, &mut memory) };
+        unsafe { allocator.destroy_image(image, &mut memory.allocation) };
 
         Ok(())
     }
 
-    fn update_buffer<T: Copy>(
+    fn update_buffer<'a, T: Copy + 'a>(
         &mut self,
         _device: &Device,
         memory: &mut Self::Memory,
-        data: &[T],
+        offset: usize,
+        data: impl IntoIterator<Item = &'a [T]>,
     ) -> RendererResult<()> {
-        let size = std::mem::size_of_val(data) as _;
-
-        let allocator = self.get_allocator()?;
-        let data_ptr = unsafe { allocator.map_memory(memory)? as *mut std::ffi::c_void };
-        let mut align =
-            unsafe { ash::util::Align::new(data_ptr, std::mem::align_of::<T>() as _, size) };
-        align.copy_from_slice(data);
-        unsafe { allocator.unmap_memory(memory) };
+        let mapped_ptr = memory
+            .mapped_ptr
+            .ok_or_else(|| RendererError::Allocator("Buffer memory is not mapped.".into()))?;
+        let size = unsafe { mapped_ptr.write(memory.size, offset as _, data)? };
+
+        if !memory.coherent {
+            self.get_allocator()?
+                .flush_allocation(&memory.allocation, offset as _, size)?;
+        }
 
         Ok(())
     }
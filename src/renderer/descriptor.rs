@@ -0,0 +1,126 @@
+use super::{
+    stats::DescriptorPoolStats,
+    vulkan::{create_vulkan_descriptor_pool, update_vulkan_descriptor_set},
+};
+use crate::RendererResult;
+use ash::{vk, Device};
+use std::collections::HashSet;
+
+/// Number of sets of the first descriptor pool.
+const INITIAL_POOL_SETS: u32 = 16;
+/// Maximum number of sets of a descriptor pool.
+const MAX_POOL_SETS: u32 = 1024;
+
+/// Allocator of the descriptor sets of the textures drawn by the renderer.
+///
+/// Sets are allocated from a chain of descriptor pools. When the last pool is full a new one
+/// twice as large is created, up to [`MAX_POOL_SETS`] sets. Freed sets are not returned to
+/// their pool but kept and reused by the next allocations.
+pub(crate) struct DescriptorAllocator {
+    set_layout: vk::DescriptorSetLayout,
+    /// Pools and the number of sets they hold. Only the last one may have free sets.
+    pools: Vec<(vk::DescriptorPool, u32)>,
+    /// Number of sets allocated from the last pool.
+    last_pool_sets: u32,
+    used_sets: HashSet<vk::DescriptorSet>,
+    recycled_sets: Vec<vk::DescriptorSet>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(
+        device: &Device,
+        set_layout: vk::DescriptorSetLayout,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> RendererResult<Self> {
+        let pool = create_vulkan_descriptor_pool(device, INITIAL_POOL_SETS, allocation_callbacks)?;
+        Ok(Self {
+            set_layout,
+            pools: vec![(pool, INITIAL_POOL_SETS)],
+            last_pool_sets: 0,
+            used_sets: HashSet::new(),
+            recycled_sets: Vec::new(),
+        })
+    }
+
+    /// Allocate a descriptor set sampling `image_view` in `image_layout` with `sampler`.
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+        image_layout: vk::ImageLayout,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> RendererResult<vk::DescriptorSet> {
+        let set = match self.recycled_sets.pop() {
+            Some(set) => set,
+            None => self.allocate_from_pool(device, allocation_callbacks)?,
+        };
+
+        update_vulkan_descriptor_set(device, set, image_view, sampler, image_layout);
+        self.used_sets.insert(set);
+
+        Ok(set)
+    }
+
+    fn allocate_from_pool(
+        &mut self,
+        device: &Device,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> RendererResult<vk::DescriptorSet> {
+        let (last_pool, last_pool_capacity) = *self.pools.last().unwrap();
+        let pool = if self.last_pool_sets < last_pool_capacity {
+            last_pool
+        } else {
+            let capacity = (last_pool_capacity * 2).min(MAX_POOL_SETS);
+            log::debug!(
+                "Descriptor pools are full, creating pool #{} of {capacity} sets",
+                self.pools.len() + 1
+            );
+            let pool = create_vulkan_descriptor_pool(device, capacity, allocation_callbacks)?;
+            self.pools.push((pool, capacity));
+            self.last_pool_sets = 0;
+            pool
+        };
+
+        let set_layouts = [self.set_layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        let set = unsafe { device.allocate_descriptor_sets(&allocate_info)?[0] };
+        self.last_pool_sets += 1;
+
+        Ok(set)
+    }
+
+    /// Release a descriptor set allocated by this allocator so it can be reused.
+    pub fn free(&mut self, set: vk::DescriptorSet) {
+        if self.used_sets.remove(&set) {
+            self.recycled_sets.push(set);
+        } else {
+            log::warn!("Freeing a descriptor set that was not allocated by the renderer");
+        }
+    }
+
+    /// Return the usage of the descriptor pools.
+    pub fn stats(&self) -> DescriptorPoolStats {
+        DescriptorPoolStats {
+            pool_count: self.pools.len(),
+            capacity: self.pools.iter().map(|(_, capacity)| capacity).sum(),
+            used_sets: self.used_sets.len() as _,
+            recycled_sets: self.recycled_sets.len() as _,
+        }
+    }
+
+    /// Destroy all pools, which frees all sets.
+    pub fn destroy(
+        &mut self,
+        device: &Device,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) {
+        self.used_sets.clear();
+        self.recycled_sets.clear();
+        for (pool, _) in self.pools.drain(..) {
+            unsafe { device.destroy_descriptor_pool(pool, allocation_callbacks) };
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! Decoding of image files into texture data.
+//!
+//! PNG and JPEG images are decoded with the image crate. KTX2 and DDS containers are read as is
+//! so their format and mip levels are preserved.
+
+use super::vulkan::{full_mip_levels, mip_level_extent, FormatBlock};
+use crate::{RendererError, RendererResult};
+use ash::vk;
+use std::convert::TryInto;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+/// Texture data decoded from an image file.
+pub(crate) struct ImageData {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+    /// Mip levels stored one after the other starting with the largest one.
+    pub data: Vec<u8>,
+}
+
+/// Decode an image file. The container is detected from the content of `bytes`.
+pub(crate) fn decode(bytes: &[u8]) -> RendererResult<ImageData> {
+    if bytes.starts_with(&KTX2_IDENTIFIER) {
+        decode_ktx2(bytes)
+    } else if bytes.starts_with(DDS_MAGIC) {
+        decode_dds(bytes)
+    } else {
+        let image = image::load_from_memory(bytes)?.into_rgba8();
+        Ok(ImageData {
+            format: vk::Format::R8G8B8A8_UNORM,
+            width: image.width(),
+            height: image.height(),
+            mip_levels: 1,
+            data: image.into_raw(),
+        })
+    }
+}
+
+fn decode_ktx2(bytes: &[u8]) -> RendererResult<ImageData> {
+    let format = vk::Format::from_raw(read_u32(bytes, 12)? as _);
+    let width = read_u32(bytes, 20)?;
+    let height = read_u32(bytes, 24)?;
+    let depth = read_u32(bytes, 28)?;
+    let layer_count = read_u32(bytes, 32)?;
+    let face_count = read_u32(bytes, 36)?;
+    let level_count = read_u32(bytes, 40)?.max(1);
+    let supercompression_scheme = read_u32(bytes, 44)?;
+
+    if format == vk::Format::UNDEFINED {
+        return Err(bad_data(
+            "KTX2 files without a Vulkan format are not supported",
+        ));
+    }
+    if supercompression_scheme != 0 {
+        return Err(bad_data("Supercompressed KTX2 files are not supported"));
+    }
+    if depth > 1 || layer_count > 1 || face_count != 1 {
+        return Err(bad_data(
+            "Only KTX2 files holding a single 2D image are supported",
+        ));
+    }
+    check_extent(width, height, level_count)?;
+
+    // The level index starts after the 80 bytes of header and index.
+    let mut data = Vec::new();
+    for level in 0..level_count as usize {
+        let entry = 80 + level * 24;
+        let offset = read_u64(bytes, entry)?;
+        let length = read_u64(bytes, entry + 8)?;
+        data.extend_from_slice(read_bytes(bytes, offset, length)?);
+    }
+
+    Ok(ImageData {
+        format,
+        width,
+        height,
+        mip_levels: level_count,
+        data,
+    })
+}
+
+fn decode_dds(bytes: &[u8]) -> RendererResult<ImageData> {
+    const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+    const DDPF_FOURCC: u32 = 0x4;
+    const DDPF_RGB: u32 = 0x40;
+    const DDSCAPS2_CUBEMAP: u32 = 0x200;
+    const DDSCAPS2_VOLUME: u32 = 0x200000;
+
+    let flags = read_u32(bytes, 8)?;
+    let height = read_u32(bytes, 12)?;
+    let width = read_u32(bytes, 16)?;
+    let mip_map_count = read_u32(bytes, 28)?;
+    let pixel_format_flags = read_u32(bytes, 80)?;
+    let four_cc = read_bytes(bytes, 84, 4)?;
+    let caps2 = read_u32(bytes, 112)?;
+
+    if caps2 & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+        return Err(bad_data(
+            "Only DDS files holding a single 2D image are supported",
+        ));
+    }
+
+    let mut data_offset = 128;
+    let format = if pixel_format_flags & DDPF_FOURCC != 0 {
+        match four_cc {
+            b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+            b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+            b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+            b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+            b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+            b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+            b"DX10" => {
+                let dxgi_format = read_u32(bytes, 128)?;
+                let array_size = read_u32(bytes, 140)?;
+                if array_size > 1 {
+                    return Err(bad_data("DDS texture arrays are not supported"));
+                }
+                data_offset += 20;
+                dxgi_to_vk_format(dxgi_format).ok_or_else(|| {
+                    bad_data(format!("Unsupported DXGI format {dxgi_format} in DDS file"))
+                })?
+            }
+            _ => {
+                return Err(bad_data(format!(
+                    "Unsupported DDS FourCC {:?}",
+                    String::from_utf8_lossy(four_cc)
+                )))
+            }
+        }
+    } else if pixel_format_flags & DDPF_RGB != 0 {
+        let bit_count = read_u32(bytes, 88)?;
+        let red_mask = read_u32(bytes, 92)?;
+        match (bit_count, red_mask) {
+            (32, 0x0000_00ff) => vk::Format::R8G8B8A8_UNORM,
+            (32, 0x00ff_0000) => vk::Format::B8G8R8A8_UNORM,
+            _ => return Err(bad_data("Unsupported uncompressed DDS pixel format")),
+        }
+    } else {
+        return Err(bad_data("Unsupported DDS pixel format"));
+    };
+
+    let mip_levels = if flags & DDSD_MIPMAPCOUNT != 0 {
+        mip_map_count.max(1)
+    } else {
+        1
+    };
+
+    check_extent(width, height, mip_levels)?;
+
+    // DDS files do not store the size of the image data so compute it from the format.
+    let block = FormatBlock::of(format)?;
+    let size = (0..mip_levels).try_fold(0usize, |size, level| {
+        let (level_width, level_height) = mip_level_extent(width, height, level);
+        block
+            .region_size(level_width, level_height)
+            .and_then(|level_size| size.checked_add(level_size))
+            .ok_or_else(|| bad_data(format!("A {width}x{height} DDS image is too large")))
+    })?;
+    let data = read_bytes(bytes, data_offset, size as _)?.to_vec();
+
+    Ok(ImageData {
+        format,
+        width,
+        height,
+        mip_levels,
+        data,
+    })
+}
+
+/// Check the extent and mip level count read from the header of an image file.
+fn check_extent(width: u32, height: u32, mip_levels: u32) -> RendererResult<()> {
+    if width == 0 || height == 0 {
+        return Err(bad_data(format!("Invalid image size {width}x{height}")));
+    }
+    let max_mip_levels = full_mip_levels(width, height);
+    if mip_levels > max_mip_levels {
+        return Err(bad_data(format!(
+            "A {width}x{height} image has at most {max_mip_levels} mip levels but got {mip_levels}"
+        )));
+    }
+    Ok(())
+}
+
+fn dxgi_to_vk_format(dxgi_format: u32) -> Option<vk::Format> {
+    let format = match dxgi_format {
+        2 => vk::Format::R32G32B32A32_SFLOAT,
+        10 => vk::Format::R16G16B16A16_SFLOAT,
+        11 => vk::Format::R16G16B16A16_UNORM,
+        16 => vk::Format::R32G32_SFLOAT,
+        24 => vk::Format::A2B10G10R10_UNORM_PACK32,
+        28 => vk::Format::R8G8B8A8_UNORM,
+        29 => vk::Format::R8G8B8A8_SRGB,
+        34 => vk::Format::R16G16_SFLOAT,
+        35 => vk::Format::R16G16_UNORM,
+        41 => vk::Format::R32_SFLOAT,
+        49 => vk::Format::R8G8_UNORM,
+        54 => vk::Format::R16_SFLOAT,
+        56 => vk::Format::R16_UNORM,
+        61 => vk::Format::R8_UNORM,
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        87 => vk::Format::B8G8R8A8_UNORM,
+        91 => vk::Format::B8G8R8A8_SRGB,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    };
+    Some(format)
+}
+
+fn read_bytes(bytes: &[u8], offset: u64, length: u64) -> RendererResult<&[u8]> {
+    offset
+        .checked_add(length)
+        .and_then(|end| bytes.get(offset.try_into().ok()?..end.try_into().ok()?))
+        .ok_or_else(|| bad_data("Unexpected end of image file"))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> RendererResult<u32> {
+    let bytes = read_bytes(bytes, offset as _, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> RendererResult<u64> {
+    let bytes = read_bytes(bytes, offset as _, 8)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn bad_data(message: impl Into<String>) -> RendererError {
+    RendererError::BadTextureData(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ktx2(format: vk::Format, width: u32, height: u32, levels: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for value in [format.as_raw() as u32, 1, width, height, 0, 0, 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.resize(80, 0);
+
+        let mut offset = 80 + levels.len() * 24;
+        for level in levels {
+            for value in [offset, level.len(), level.len()] {
+                bytes.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+            offset += level.len();
+        }
+        levels
+            .iter()
+            .for_each(|level| bytes.extend_from_slice(level));
+        bytes
+    }
+
+    fn dds(four_cc: &[u8; 4], width: u32, height: u32, mip_levels: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; 128];
+        bytes[..4].copy_from_slice(DDS_MAGIC);
+        let mut write = |offset: usize, value: u32| {
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes())
+        };
+        write(4, 124);
+        write(8, 0x1007 | 0x20000);
+        write(12, height);
+        write(16, width);
+        write(28, mip_levels);
+        write(76, 32);
+        write(80, 0x4);
+        bytes[84..88].copy_from_slice(four_cc);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn is_bad_data(result: RendererResult<ImageData>) -> bool {
+        matches!(result, Err(RendererError::BadTextureData(_)))
+    }
+
+    #[test]
+    fn decode_ktx2_levels() {
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 2, 2, &[&[1; 16], &[2; 4]]);
+        let image = decode(&bytes).unwrap();
+
+        assert_eq!(image.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!((image.width, image.height, image.mip_levels), (2, 2, 2));
+        assert_eq!(image.data[..16], [1; 16]);
+        assert_eq!(image.data[16..], [2; 4]);
+    }
+
+    #[test]
+    fn decode_ktx2_truncated() {
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 2, 2, &[&[1; 16], &[2; 4]]);
+        assert!(is_bad_data(decode(&bytes[..bytes.len() - 1])));
+        assert!(is_bad_data(decode(&bytes[..40])));
+    }
+
+    #[test]
+    fn decode_ktx2_absurd_header() {
+        let mut bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 2, 2, &[&[1; 16]]);
+        bytes[40..44].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(is_bad_data(decode(&bytes)));
+
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, 0, 2, &[&[]]);
+        assert!(is_bad_data(decode(&bytes)));
+    }
+
+    #[test]
+    fn decode_dds_levels() {
+        // Levels of 8x8, 4x4, 2x2 and 1x1 texels each hold at least one 8 bytes block.
+        let data = (0..56).collect::<Vec<u8>>();
+        let image = decode(&dds(b"DXT1", 8, 8, 4, &data)).unwrap();
+
+        assert_eq!(image.format, vk::Format::BC1_RGBA_UNORM_BLOCK);
+        assert_eq!((image.width, image.height, image.mip_levels), (8, 8, 4));
+        assert_eq!(image.data, data);
+    }
+
+    #[test]
+    fn decode_dds_truncated() {
+        let bytes = dds(b"DXT1", 8, 8, 4, &[0; 56]);
+        assert!(is_bad_data(decode(&bytes[..bytes.len() - 1])));
+        assert!(is_bad_data(decode(&bytes[..100])));
+    }
+
+    #[test]
+    fn decode_dds_absurd_header() {
+        assert!(is_bad_data(decode(&dds(b"DXT1", 8, 8, 40, &[0; 56]))));
+        assert!(is_bad_data(decode(&dds(b"DXT1", 8, 0, 1, &[0; 56]))));
+        assert!(is_bad_data(decode(&dds(
+            b"DXT5",
+            u32::MAX,
+            u32::MAX,
+            32,
+            &[0; 56]
+        ))));
+    }
+}
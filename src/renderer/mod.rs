@@ -1,29 +1,43 @@
-mod allocator;
+pub mod allocator;
+mod descriptor;
+#[cfg(feature = "image-loading")]
+mod loader;
+mod retire;
+mod sampler;
+mod staging;
+mod stats;
+mod transfer;
 pub mod vulkan;
 
 use crate::RendererError;
-use ash::{vk, Device};
+use ash::{vk, Device, Instance};
+use descriptor::DescriptorAllocator;
 use imgui::{Context, DrawCmd, DrawCmdParams, DrawData, TextureId, Textures};
 use mesh::*;
+use retire::{Retired, RetirementQueue};
+use sampler::SamplerCache;
+use staging::StagingPool;
 use ultraviolet::projection::orthographic_vk;
 use vulkan::*;
 
-use self::allocator::Allocator;
-
-#[cfg(not(any(feature = "gpu-allocator", feature = "vk-mem")))]
-use ash::Instance;
+pub use self::allocator::{Allocate, DefaultAllocator, MemoryLocation};
+pub use self::sampler::{ImageSampler, SamplerDesc};
+pub use self::stats::*;
+pub use self::transfer::{TransferQueue, UploadSignal};
 
 #[cfg(feature = "gpu-allocator")]
-use {
-    gpu_allocator::vulkan::Allocator as GpuAllocator,
-    std::sync::{Arc, Mutex},
-};
+pub use self::allocator::GpuAllocator;
 
 #[cfg(feature = "vk-mem")]
-use {
-    std::sync::{Arc, Mutex},
-    vk_mem::Allocator as VkMemAllocator,
-};
+pub use self::allocator::VkMemAllocator;
+
+#[cfg(feature = "leak-tracker")]
+pub use self::allocator::{LeakTracker, LiveAllocation};
+
+#[cfg(any(feature = "gpu-allocator", feature = "vk-mem"))]
+use std::sync::{Arc, Mutex};
+
+use std::collections::{HashMap, HashSet};
 
 /// Convenient return type for function that can return a [`RendererError`].
 ///
@@ -42,6 +56,33 @@ pub struct Options {
     /// Note that depth writes are always disabled when enable_depth_test is false.
     /// See <https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/VkPipelineDepthStencilStateCreateInfo.html>
     pub enable_depth_write: bool,
+    /// Resizing policy of the vertex and index buffers.
+    pub buffer_policy: BufferPolicy,
+    /// If true vertex and index buffers are allocated in device local memory.
+    ///
+    /// When the allocator supports host visible device memory (resizable BAR) the buffers are
+    /// written directly. Otherwise they are uploaded from staging buffers. In both cases
+    /// [`Renderer::cmd_upload`] must be called before the render pass, ahead of [`Renderer::cmd_draw`].
+    pub device_local_buffers: bool,
+    /// Host memory allocation callbacks used to create and destroy all Vulkan objects of the renderer.
+    ///
+    /// They are also used by the allocators created by [`Renderer::with_default_allocator`] and
+    /// `Renderer::with_gpu_allocator`. The vk-mem allocator uses the callbacks it was created with
+    /// and allocators provided to [`Renderer::with_allocator`] must be configured by the caller.
+    ///
+    /// The callbacks must stay valid until the renderer is dropped.
+    pub allocation_callbacks: Option<vk::AllocationCallbacks<'static>>,
+    /// If true a full mip chain is generated on the gpu for textures created by the renderer
+    /// from a single mip level, and generated again after [`Renderer::update_texture_region`].
+    ///
+    /// Levels are generated with [vkCmdBlitImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdBlitImage.html)
+    /// so the queues used to create and update textures must support graphics operations.
+    /// Formats that do not support linear filtered blits, such as block compressed formats,
+    /// keep their single level.
+    ///
+    /// Samplers are shared between textures and do not clamp their LOD range. The range sampled
+    /// matches the generated chain because the view of each texture covers exactly its levels.
+    pub generate_mipmaps: bool,
 }
 
 impl Default for Options {
@@ -50,7 +91,85 @@ impl Default for Options {
             in_flight_frames: 1,
             enable_depth_test: false,
             enable_depth_write: false,
+            buffer_policy: Default::default(),
+            device_local_buffers: false,
+            allocation_callbacks: None,
+            generate_mipmaps: false,
+        }
+    }
+}
+
+/// Resizing policy of the vertex and index buffers of each frame in flight.
+///
+/// Buffers grow with some headroom when draw data does not fit and shrink back once their
+/// usage stayed far below their capacity for a while.
+#[derive(Debug, Clone, Copy)]
+pub struct BufferPolicy {
+    /// Factor applied to the vertex/index count when a buffer grows.
+    ///
+    /// Values below 1.0 are treated as 1.0.
+    pub growth_factor: f32,
+    /// Minimum number of vertices a vertex buffer can hold.
+    pub min_vertex_count: usize,
+    /// Minimum number of indices an index buffer can hold.
+    pub min_index_count: usize,
+    /// Fraction of its capacity under which a buffer is considered underused.
+    pub shrink_threshold: f32,
+    /// Number of consecutive updates a buffer must be underused before it is shrunk.
+    ///
+    /// Each frame in flight has its own buffers so they are updated once every `in_flight_frames`
+    /// frames. 0 disables shrinking.
+    pub shrink_after_frames: u32,
+}
+
+impl Default for BufferPolicy {
+    fn default() -> Self {
+        Self {
+            growth_factor: 1.5,
+            min_vertex_count: 1024,
+            min_index_count: 2048,
+            shrink_threshold: 0.25,
+            shrink_after_frames: 120,
+        }
+    }
+}
+
+impl BufferPolicy {
+    /// Capacity of a buffer that needs to hold `count` elements.
+    fn capacity(&self, count: usize, min_count: usize) -> usize {
+        let capacity = (count as f32 * self.growth_factor.max(1.0)).ceil() as usize;
+        capacity.max(count).max(min_count)
+    }
+
+    /// Return the new capacity of a buffer if it needs to be resized.
+    ///
+    /// `underused_frames` tracks the number of consecutive updates the buffer was underused.
+    fn resize(
+        &self,
+        capacity: usize,
+        count: usize,
+        min_count: usize,
+        underused_frames: &mut u32,
+    ) -> Option<usize> {
+        if count > capacity {
+            *underused_frames = 0;
+            return Some(self.capacity(count, min_count));
+        }
+
+        let target = self.capacity(count, min_count);
+        let underused = (count as f32) < capacity as f32 * self.shrink_threshold;
+        if self.shrink_after_frames == 0 || !underused || target >= capacity {
+            *underused_frames = 0;
+            return None;
         }
+
+        *underused_frames += 1;
+        if *underused_frames < self.shrink_after_frames {
+            return None;
+        }
+
+        *underused_frames = 0;
+        Some(target)
     }
 }
 
@@ -69,22 +188,37 @@ pub struct DynamicRendering {
 /// The renderer holds a set of vertex/index buffers per in flight frames. Vertex and index buffers
 /// are resized at each call to [`cmd_draw`] if draw data does not fit.
 ///
+/// All buffers and images are allocated through `A`. See [`Allocate`] for the available allocators.
+///
 /// [`cmd_draw`]: #method.cmd_draw
-pub struct Renderer {
+pub struct Renderer<A: Allocate = DefaultAllocator> {
+    instance: Instance,
+    physical_device: vk::PhysicalDevice,
     device: Device,
-    allocator: Allocator,
+    allocator: A,
     pipeline: vk::Pipeline,
     pipeline_layout: vk::PipelineLayout,
     descriptor_set_layout: vk::DescriptorSetLayout,
-    fonts_texture: Option<Texture>,
-    descriptor_pool: vk::DescriptorPool,
+    fonts_texture: Option<Texture<A>>,
+    descriptors: DescriptorAllocator,
     descriptor_set: vk::DescriptorSet,
     textures: Textures<vk::DescriptorSet>,
+    managed_textures: HashMap<TextureId, Texture<A>>,
+    registered_images: HashSet<TextureId>,
+    samplers: SamplerCache,
     options: Options,
-    frames: Option<Frames>,
+    frames: Option<Frames<A>>,
+    uploaded: bool,
+    frame_count: u64,
+    // Resources destroyed once the frames in flight that may use them have completed.
+    retired: RetirementQueue<A>,
+    // Staging buffers shared by all uploads.
+    staging: StagingPool<A>,
+    // Textures uploaded by a transfer queue and not yet acquired by the graphics queue.
+    pending_acquires: Vec<(TextureId, QueueFamilyTransfer)>,
 }
 
-impl Renderer {
+impl Renderer<DefaultAllocator> {
     /// Initialize and return a new instance of the renderer.
     ///
     /// At initialization all Vulkan resources are initialized and font texture is created and
@@ -96,10 +230,10 @@ impl Renderer {
     /// * `physical_device` - A Vulkan physical device.
     /// * `device` - A Vulkan device.
     /// * `queue` - A Vulkan queue.
-    ///             It will be used to submit commands during initialization to upload
-    ///             data to the gpu. The type of queue must be supported by the following
-    ///             commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
-    ///             [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
+    ///   It will be used to submit commands during initialization to upload
+    ///   data to the gpu. The type of queue must be supported by the following
+    ///   commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
+    ///   [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
     /// * `command_pool` - A Vulkan command pool used to allocate command buffers to upload textures to the gpu.
     /// * `render_pass` - *without dynamic-rendering feature* - The render pass used to render the gui.
     /// * `dynamic_rendering` - *with dynamic-rendering feature* - Dynamic rendeing parameters
@@ -110,7 +244,8 @@ impl Renderer {
     ///
     /// * [`RendererError`] - If the number of in flight frame in incorrect.
     /// * [`RendererError`] - If any Vulkan or io error is encountered during initialization.
-    #[cfg(not(any(feature = "gpu-allocator", feature = "vk-mem")))]
+    #[allow(clippy::too_many_arguments)]
+    #[track_caller]
     pub fn with_default_allocator(
         instance: &Instance,
         physical_device: vk::PhysicalDevice,
@@ -122,14 +257,18 @@ impl Renderer {
         imgui: &mut Context,
         options: Option<Options>,
     ) -> RendererResult<Self> {
-        let memory_properties =
-            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let mut allocator = DefaultAllocator::new(instance, physical_device);
+        if let Some(allocation_callbacks) = options.and_then(|o| o.allocation_callbacks) {
+            allocator = allocator.with_allocation_callbacks(allocation_callbacks);
+        }
 
-        Self::from_allocator(
+        Self::with_allocator(
+            allocator,
+            instance,
+            physical_device,
             device,
             queue,
             command_pool,
-            Allocator::new(memory_properties),
             #[cfg(not(feature = "dynamic-rendering"))]
             render_pass,
             #[cfg(feature = "dynamic-rendering")]
@@ -138,7 +277,10 @@ impl Renderer {
             options,
         )
     }
+}
 
+#[cfg(feature = "gpu-allocator")]
+impl Renderer<GpuAllocator> {
     /// Initialize and return a new instance of the renderer.
     ///
     /// At initialization all Vulkan resources are initialized and font texture is created and
@@ -147,12 +289,14 @@ impl Renderer {
     /// # Arguments
     ///
     /// * `gpu_allocator` - The allocator that will be used to allocator buffer and image memory.
+    /// * `instance` - A reference to a Vulkan instance.
+    /// * `physical_device` - A Vulkan physical device.
     /// * `device` - A Vulkan device.
     /// * `queue` - A Vulkan queue.
-    ///             It will be used to submit commands during initialization to upload
-    ///             data to the gpu. The type of queue must be supported by the following
-    ///             commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
-    ///             [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
+    ///   It will be used to submit commands during initialization to upload
+    ///   data to the gpu. The type of queue must be supported by the following
+    ///   commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
+    ///   [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
     /// * `command_pool` - A Vulkan command pool used to allocate command buffers to upload textures to the gpu.
     /// * `render_pass` - *without dynamic-rendering feature* - The render pass used to render the gui.
     /// * `dynamic_rendering` - *with dynamic-rendering feature* - Dynamic rendeing parameters
@@ -163,9 +307,12 @@ impl Renderer {
     ///
     /// * [`RendererError`] - If the number of in flight frame in incorrect.
     /// * [`RendererError`] - If any Vulkan or io error is encountered during initialization.
-    #[cfg(feature = "gpu-allocator")]
+    #[allow(clippy::too_many_arguments)]
+    #[track_caller]
     pub fn with_gpu_allocator(
-        gpu_allocator: Arc<Mutex<GpuAllocator>>, // TODO: Another way ?
+        gpu_allocator: Arc<Mutex<gpu_allocator::vulkan::Allocator>>,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
         device: Device,
         queue: vk::Queue,
         command_pool: vk::CommandPool,
@@ -174,11 +321,18 @@ impl Renderer {
         imgui: &mut Context,
         options: Option<Options>,
     ) -> RendererResult<Self> {
-        Self::from_allocator(
+        let mut allocator = GpuAllocator::new(gpu_allocator);
+        if let Some(allocation_callbacks) = options.and_then(|o| o.allocation_callbacks) {
+            allocator = allocator.with_allocation_callbacks(allocation_callbacks);
+        }
+
+        Self::with_allocator(
+            allocator,
+            instance,
+            physical_device,
             device,
             queue,
             command_pool,
-            Allocator::new(gpu_allocator),
             #[cfg(not(feature = "dynamic-rendering"))]
             render_pass,
             #[cfg(feature = "dynamic-rendering")]
@@ -187,7 +341,10 @@ impl Renderer {
             options,
         )
     }
+}
 
+#[cfg(feature = "vk-mem")]
+impl Renderer<VkMemAllocator> {
     /// Initialize and return a new instance of the renderer.
     ///
     /// At initialization all Vulkan resources are initialized and font texture is created and
@@ -196,12 +353,14 @@ impl Renderer {
     /// # Arguments
     ///
     /// * `vk_mem_allocator` - The allocator that will be used to allocator buffer and image memory.
+    /// * `instance` - A reference to a Vulkan instance.
+    /// * `physical_device` - A Vulkan physical device.
     /// * `device` - A Vulkan device.
     /// * `queue` - A Vulkan queue.
-    ///             It will be used to submit commands during initialization to upload
-    ///             data to the gpu. The type of queue must be supported by the following
-    ///             commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
-    ///             [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
+    ///   It will be used to submit commands during initialization to upload
+    ///   data to the gpu. The type of queue must be supported by the following
+    ///   commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
+    ///   [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
     /// * `command_pool` - A Vulkan command pool used to allocate command buffers to upload textures to the gpu.
     /// * `render_pass` - *without dynamic-rendering feature* - The render pass used to render the gui.
     /// * `dynamic_rendering` - *with dynamic-rendering feature* - Dynamic rendeing parameters
@@ -212,9 +371,12 @@ impl Renderer {
     ///
     /// * [`RendererError`] - If the number of in flight frame in incorrect.
     /// * [`RendererError`] - If any Vulkan or io error is encountered during initialization.
-    #[cfg(feature = "vk-mem")]
+    #[allow(clippy::too_many_arguments)]
+    #[track_caller]
     pub fn with_vk_mem_allocator(
-        vk_mem_allocator: Arc<Mutex<VkMemAllocator>>, // TODO: Another way ?
+        vk_mem_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
         device: Device,
         queue: vk::Queue,
         command_pool: vk::CommandPool,
@@ -223,11 +385,13 @@ impl Renderer {
         imgui: &mut Context,
         options: Option<Options>,
     ) -> RendererResult<Self> {
-        Self::from_allocator(
+        Self::with_allocator(
+            VkMemAllocator::new(vk_mem_allocator),
+            instance,
+            physical_device,
             device,
             queue,
             command_pool,
-            Allocator::new(vk_mem_allocator),
             #[cfg(not(feature = "dynamic-rendering"))]
             render_pass,
             #[cfg(feature = "dynamic-rendering")]
@@ -236,12 +400,55 @@ impl Renderer {
             options,
         )
     }
+}
 
-    fn from_allocator(
+#[cfg(feature = "leak-tracker")]
+impl<A: Allocate> Renderer<LeakTracker<A>> {
+    /// Return the buffers and images allocated by the renderer that are still alive.
+    ///
+    /// Useful to find out which resource is leaking. Resources still alive when the
+    /// renderer is dropped are logged.
+    pub fn live_allocations(&self) -> impl Iterator<Item = &LiveAllocation> {
+        self.allocator.live_allocations()
+    }
+}
+
+impl<A: Allocate> Renderer<A> {
+    /// Initialize and return a new instance of the renderer using the provided allocator.
+    ///
+    /// At initialization all Vulkan resources are initialized and font texture is created and
+    /// uploaded to the gpu. Vertex and index buffers are not created yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `allocator` - The allocator that will be used to allocator buffer and image memory.
+    /// * `instance` - A reference to a Vulkan instance.
+    /// * `physical_device` - A Vulkan physical device.
+    /// * `device` - A Vulkan device.
+    /// * `queue` - A Vulkan queue.
+    ///   It will be used to submit commands during initialization to upload
+    ///   data to the gpu. The type of queue must be supported by the following
+    ///   commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
+    ///   [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
+    /// * `command_pool` - A Vulkan command pool used to allocate command buffers to upload textures to the gpu.
+    /// * `render_pass` - *without dynamic-rendering feature* - The render pass used to render the gui.
+    /// * `dynamic_rendering` - *with dynamic-rendering feature* - Dynamic rendeing parameters
+    /// * `imgui` - The imgui context.
+    /// * `options` - Optional parameters of the renderer.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError`] - If the number of in flight frame in incorrect.
+    /// * [`RendererError`] - If any Vulkan or io error is encountered during initialization.
+    #[allow(clippy::too_many_arguments)]
+    #[track_caller]
+    pub fn with_allocator(
+        mut allocator: A,
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
         device: Device,
         queue: vk::Queue,
         command_pool: vk::CommandPool,
-        mut allocator: Allocator,
         #[cfg(not(feature = "dynamic-rendering"))] render_pass: vk::RenderPass,
         #[cfg(feature = "dynamic-rendering")] dynamic_rendering: DynamicRendering,
         imgui: &mut Context,
@@ -257,11 +464,15 @@ impl Renderer {
             )));
         }
 
+        let allocation_callbacks = options.allocation_callbacks.as_ref();
+
         // Descriptor set layout
-        let descriptor_set_layout = create_vulkan_descriptor_set_layout(&device)?;
+        let descriptor_set_layout =
+            create_vulkan_descriptor_set_layout(&device, allocation_callbacks)?;
 
         // Pipeline and layout
-        let pipeline_layout = create_vulkan_pipeline_layout(&device, descriptor_set_layout)?;
+        let pipeline_layout =
+            create_vulkan_pipeline_layout(&device, descriptor_set_layout, allocation_callbacks)?;
         let pipeline = create_vulkan_pipeline(
             &device,
             pipeline_layout,
@@ -273,15 +484,21 @@ impl Renderer {
         )?;
 
         // Fonts texture
+        let mut samplers = SamplerCache::new(instance, physical_device);
+        let mut staging = StagingPool::new();
         let fonts_texture = {
             let fonts = imgui.fonts();
             let atlas_texture = fonts.build_rgba32_texture();
+            let sampler = samplers.get(&device, &SamplerDesc::default(), allocation_callbacks)?;
 
             Texture::from_rgba8(
                 &device,
                 queue,
                 command_pool,
                 &mut allocator,
+                &mut staging,
+                allocation_callbacks,
+                sampler,
                 atlas_texture.width,
                 atlas_texture.height,
                 atlas_texture.data,
@@ -291,33 +508,44 @@ impl Renderer {
         let fonts = imgui.fonts();
         fonts.tex_id = TextureId::from(usize::MAX);
 
-        // Descriptor pool
-        let descriptor_pool = create_vulkan_descriptor_pool(&device, 1)?;
+        // Descriptor pools, growing when textures are added
+        let mut descriptors =
+            DescriptorAllocator::new(&device, descriptor_set_layout, allocation_callbacks)?;
 
         // Descriptor set
-        let descriptor_set = create_vulkan_descriptor_set(
+        let descriptor_set = descriptors.allocate(
             &device,
-            descriptor_set_layout,
-            descriptor_pool,
             fonts_texture.image_view,
             fonts_texture.sampler,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            allocation_callbacks,
         )?;
 
         // Textures
         let textures = Textures::new();
 
         Ok(Self {
+            instance: instance.clone(),
+            physical_device,
             device,
             allocator,
             pipeline,
             pipeline_layout,
             descriptor_set_layout,
             fonts_texture: Some(fonts_texture),
-            descriptor_pool,
+            descriptors,
             descriptor_set,
             textures,
+            managed_textures: HashMap::new(),
+            registered_images: HashSet::new(),
+            samplers,
             options,
             frames: None,
+            uploaded: false,
+            frame_count: 0,
+            retired: RetirementQueue::new(),
+            staging,
+            pending_acquires: Vec::new(),
         })
     }
 
@@ -336,7 +564,10 @@ impl Renderer {
     /// * [`RendererError`] - If any Vulkan error is encountered during pipeline creation.
     #[cfg(not(feature = "dynamic-rendering"))]
     pub fn set_render_pass(&mut self, render_pass: vk::RenderPass) -> RendererResult<()> {
-        unsafe { self.device.destroy_pipeline(self.pipeline, None) };
+        unsafe {
+            self.device
+                .destroy_pipeline(self.pipeline, self.options.allocation_callbacks.as_ref())
+        };
         self.pipeline = create_vulkan_pipeline(
             &self.device,
             self.pipeline_layout,
@@ -348,7 +579,9 @@ impl Renderer {
 
     /// Returns the texture mapping used by the renderer to lookup textures.
     ///
-    /// Textures are provided by the application as `vk::DescriptorSet`s.
+    /// Textures are provided by the application as `vk::DescriptorSet`s. Images owned by the
+    /// application can also be registered with [`Renderer::register_image`], which allocates
+    /// their descriptor sets from the renderer's descriptor pools.
     ///
     /// # Example
     ///
@@ -370,6 +603,777 @@ impl Renderer {
         &mut self.textures
     }
 
+    /// Create a texture from an `u8` array containing an rgba image and return its id.
+    ///
+    /// The texture is owned by the renderer. Its image data is device local and its format is
+    /// R8G8B8A8_UNORM. Its descriptor set is allocated from the renderer's descriptor pool with
+    /// the renderer's descriptor set layout and registered in [`Renderer::textures`]. It is
+    /// sampled with the default [`SamplerDesc`], see [`Renderer::create_texture_with_mip_levels`]
+    /// to use another sampler.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue` - A Vulkan queue.
+    ///   It will be used to submit commands to upload the texture to the gpu. The type of queue
+    ///   must be supported by the following commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
+    ///   [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
+    /// * `command_pool` - A Vulkan command pool used to allocate command buffers to upload textures to the gpu.
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    /// * `data` - The image data.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError`] - If any error is encountered during texture creation.
+    #[track_caller]
+    pub fn create_texture(
+        &mut self,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> RendererResult<TextureId> {
+        self.create_texture_with_format(
+            queue,
+            command_pool,
+            vk::Format::R8G8B8A8_UNORM,
+            width,
+            height,
+            data,
+        )
+    }
+
+    /// Create a texture from an `u8` array containing an image of the given format and return its id.
+    ///
+    /// Same as [`Renderer::create_texture`] for images that are not R8G8B8A8_UNORM. The following
+    /// uncompressed color formats are supported, provided the device can sample them:
+    ///
+    /// * R8_UNORM, R8_SRGB, R8G8_UNORM, R8G8_SRGB
+    /// * R8G8B8A8_UNORM, R8G8B8A8_SRGB, B8G8R8A8_UNORM, B8G8R8A8_SRGB, A2B10G10R10_UNORM_PACK32
+    /// * R16_UNORM, R16_SFLOAT, R16G16_UNORM, R16G16_SFLOAT, R16G16B16A16_UNORM, R16G16B16A16_SFLOAT
+    /// * R32_SFLOAT, R32G32_SFLOAT, R32G32B32A32_SFLOAT
+    ///
+    /// Texels are sampled as is. Single channel images are drawn in shades of red.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue` - A Vulkan queue.
+    ///   It will be used to submit commands to upload the texture to the gpu. The type of queue
+    ///   must be supported by the following commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
+    ///   [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
+    /// * `command_pool` - A Vulkan command pool used to allocate command buffers to upload textures to the gpu.
+    /// * `format` - The format of the image.
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    /// * `data` - The image data, tightly packed texels of `format`.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::UnsupportedFormat`] - If the format is not supported by the renderer or the device.
+    /// * [`RendererError::BadTextureData`] - If the image is empty or `data` does not match the size of the image.
+    /// * [`RendererError`] - If any error is encountered during texture creation.
+    #[track_caller]
+    pub fn create_texture_with_format(
+        &mut self,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> RendererResult<TextureId> {
+        self.create_texture_with_mip_levels(
+            queue,
+            command_pool,
+            format,
+            width,
+            height,
+            1,
+            &SamplerDesc::default(),
+            data,
+        )
+    }
+
+    /// Create a texture from an `u8` array containing an image and its mip levels and return its id.
+    ///
+    /// Same as [`Renderer::create_texture_with_format`] but all mip levels are provided, the
+    /// sampler is described by `sampler` and block compressed formats are supported:
+    ///
+    /// * BC1 to BC7, which require the `textureCompressionBC` device feature
+    /// * ETC2 and EAC, which require the `textureCompressionETC2` device feature
+    /// * ASTC LDR, which require the `textureCompressionASTC_LDR` device feature
+    ///
+    /// The required feature must be enabled when creating the device. Textures with equal
+    /// sampler descriptions share the same `vk::Sampler`.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue` - A Vulkan queue.
+    ///   It will be used to submit commands to upload the texture to the gpu. The type of queue
+    ///   must be supported by the following commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
+    ///   [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
+    /// * `command_pool` - A Vulkan command pool used to allocate command buffers to upload textures to the gpu.
+    /// * `format` - The format of the image.
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    /// * `mip_levels` - The number of mip levels in `data`.
+    /// * `sampler` - The description of the sampler of the texture.
+    /// * `data` - The image data. Mip levels are stored one after the other starting with the
+    ///   largest one, each as tightly packed texels or blocks of `format`.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::MissingDeviceFeature`] - If the device does not support the compression feature of the format.
+    /// * [`RendererError::UnsupportedFormat`] - If the format is not supported by the renderer or the device.
+    /// * [`RendererError::BadTextureData`] - If the image is empty or `data` does not match the size of the mip levels.
+    /// * [`RendererError`] - If any error is encountered during texture creation.
+    #[allow(clippy::too_many_arguments)]
+    #[track_caller]
+    pub fn create_texture_with_mip_levels(
+        &mut self,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        sampler: &SamplerDesc,
+        data: &[u8],
+    ) -> RendererResult<TextureId> {
+        let (sampler, generate_mipmaps) = self.prepare_texture(format, mip_levels, sampler)?;
+
+        let texture = Texture::from_data(
+            &self.device,
+            queue,
+            command_pool,
+            &mut self.allocator,
+            &mut self.staging,
+            self.options.allocation_callbacks.as_ref(),
+            sampler,
+            format,
+            width,
+            height,
+            mip_levels,
+            generate_mipmaps,
+            data,
+        )?;
+
+        self.insert_managed_texture(texture)
+    }
+
+    /// Load an image file and create a texture owned by the renderer.
+    ///
+    /// See [`Renderer::load_texture_from_memory`] for the supported files.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue` - The queue used to copy image data on the gpu.
+    /// * `command_pool` - A command pool used to allocate command buffers for the copy.
+    /// * `sampler` - The description of the sampler of the texture.
+    /// * `path` - The path of the image file.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::Io`] - If the file cannot be read.
+    /// * [`RendererError`] - If the file cannot be decoded or the texture cannot be created.
+    #[cfg(feature = "image-loading")]
+    #[track_caller]
+    pub fn load_texture_from_file<P: AsRef<std::path::Path>>(
+        &mut self,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        sampler: &SamplerDesc,
+        path: P,
+    ) -> RendererResult<TextureId> {
+        let bytes = std::fs::read(path)?;
+        self.load_texture_from_memory(queue, command_pool, sampler, &bytes)
+    }
+
+    /// Decode an image file and create a texture owned by the renderer.
+    ///
+    /// PNG and JPEG images are decoded as [`vk::Format::R8G8B8A8_UNORM`]. KTX2 and DDS files keep
+    /// their format and mip levels. They must hold a single 2D image without supercompression.
+    ///
+    /// The texture is destroyed with [`Renderer::destroy_texture`] like the ones created with
+    /// [`Renderer::create_texture`].
+    ///
+    /// # Arguments
+    ///
+    /// * `queue` - The queue used to copy image data on the gpu.
+    /// * `command_pool` - A command pool used to allocate command buffers for the copy.
+    /// * `sampler` - The description of the sampler of the texture.
+    /// * `bytes` - The content of the image file. The container is detected from its content.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::Image`] - If a PNG or JPEG image cannot be decoded.
+    /// * [`RendererError::BadTextureData`] - If a KTX2 or DDS file is malformed or not supported.
+    /// * [`RendererError`] - If any error is encountered during texture creation.
+    #[cfg(feature = "image-loading")]
+    #[track_caller]
+    pub fn load_texture_from_memory(
+        &mut self,
+        queue: vk::Queue,
+        command_pool: vk::CommandPool,
+        sampler: &SamplerDesc,
+        bytes: &[u8],
+    ) -> RendererResult<TextureId> {
+        let image = loader::decode(bytes)?;
+        self.create_texture_with_mip_levels(
+            queue,
+            command_pool,
+            image.format,
+            image.width,
+            image.height,
+            image.mip_levels,
+            sampler,
+            &image.data,
+        )
+    }
+
+    /// Record the creation of a texture from an `u8` array containing an rgba image and return its id.
+    ///
+    /// Same as [`Renderer::create_texture`] but the upload is recorded into `command_buffer`
+    /// instead of being submitted and waited for. The texture can be drawn by frames submitted
+    /// after `command_buffer`. The staging memory is recycled once the frame it was recorded
+    /// in is no longer in flight. Commands must be recorded outside of a render pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `command_buffer` - The Vulkan command buffer that command will be recorded to.
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    /// * `data` - The image data.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError`] - If any error is encountered during texture creation.
+    #[track_caller]
+    pub fn cmd_create_texture(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> RendererResult<TextureId> {
+        self.cmd_create_texture_with_mip_levels(
+            command_buffer,
+            vk::Format::R8G8B8A8_UNORM,
+            width,
+            height,
+            1,
+            &SamplerDesc::default(),
+            data,
+        )
+    }
+
+    /// Record the creation of a texture from an `u8` array containing an image and its mip levels and return its id.
+    ///
+    /// Same as [`Renderer::create_texture_with_mip_levels`] but the upload is recorded into
+    /// `command_buffer` instead of being submitted and waited for. The texture can be drawn by
+    /// frames submitted after `command_buffer`. The staging memory is recycled once the frame
+    /// it was recorded in is no longer in flight. Commands must be recorded outside of a render pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `command_buffer` - The Vulkan command buffer that command will be recorded to.
+    /// * `format` - The format of the image.
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    /// * `mip_levels` - The number of mip levels in `data`.
+    /// * `sampler` - The description of the sampler of the texture.
+    /// * `data` - The image data. Mip levels are stored one after the other starting with the
+    ///   largest one, each as tightly packed texels or blocks of `format`.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::MissingDeviceFeature`] - If the device does not support the compression feature of the format.
+    /// * [`RendererError::UnsupportedFormat`] - If the format is not supported by the renderer or the device.
+    /// * [`RendererError::BadTextureData`] - If the image is empty or `data` does not match the size of the mip levels.
+    /// * [`RendererError`] - If any error is encountered during texture creation.
+    #[allow(clippy::too_many_arguments)]
+    #[track_caller]
+    pub fn cmd_create_texture_with_mip_levels(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        sampler: &SamplerDesc,
+        data: &[u8],
+    ) -> RendererResult<TextureId> {
+        let (sampler, generate_mipmaps) = self.prepare_texture(format, mip_levels, sampler)?;
+
+        let (texture, region) = Texture::cmd_from_data(
+            &self.device,
+            &mut self.allocator,
+            &mut self.staging,
+            self.options.allocation_callbacks.as_ref(),
+            sampler,
+            command_buffer,
+            format,
+            width,
+            height,
+            mip_levels,
+            generate_mipmaps,
+            None,
+            data,
+        )?;
+        self.retire(Retired::Staging(region));
+
+        self.insert_managed_texture(texture)
+    }
+
+    /// Upload a texture from an `u8` array containing an image and its mip levels with a
+    /// dedicated transfer queue and return its id.
+    ///
+    /// The upload is submitted to `transfer.queue` and not waited for. `signal` is signaled
+    /// when it completes. If the transfer queue belongs to another family than the graphics
+    /// queue, the image is released by the transfer queue and must then be acquired by the
+    /// graphics queue with [`Renderer::cmd_acquire_textures`]. Either way the submission drawing
+    /// the texture must wait on `signal`. The staging memory and command buffer are released
+    /// once the upload completes.
+    ///
+    /// The command buffer is freed into `transfer.command_pool` by the renderer, so the pool must
+    /// outlive the renderer, or at least the last call to [`Renderer::release_retired_resources`]
+    /// made after the upload completed.
+    ///
+    /// # Arguments
+    ///
+    /// * `transfer` - The queue and command pool the upload is submitted with. The pool must
+    ///   outlive the renderer, see above.
+    /// * `signal` - The semaphore signaled when the upload completes.
+    /// * `format` - The format of the image.
+    /// * `width` - The width of the image.
+    /// * `height` - The height of the image.
+    /// * `mip_levels` - The number of mip levels in `data`.
+    /// * `sampler` - The description of the sampler of the texture.
+    /// * `data` - The image data. Mip levels are stored one after the other starting with the
+    ///   largest one, each as tightly packed texels or blocks of `format`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let texture_id = renderer.create_texture_with_transfer_queue(
+    ///     &transfer_queue,
+    ///     UploadSignal::Timeline(upload_semaphore, upload_value),
+    ///     vk::Format::R8G8B8A8_SRGB,
+    ///     width,
+    ///     height,
+    ///     1,
+    ///     &SamplerDesc::default(),
+    ///     &pixels,
+    /// )?;
+    ///
+    /// // Before the render pass, in a submission waiting on `upload_value`.
+    /// renderer.cmd_acquire_textures(command_buffer);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::MissingDeviceFeature`] - If the device does not support the compression feature of the format.
+    /// * [`RendererError::UnsupportedFormat`] - If the format is not supported by the renderer or the device.
+    /// * [`RendererError::BadTextureData`] - If the image is empty or `data` does not match the size of the mip levels.
+    /// * [`RendererError`] - If any error is encountered during texture creation.
+    #[allow(clippy::too_many_arguments)]
+    #[track_caller]
+    pub fn create_texture_with_transfer_queue(
+        &mut self,
+        transfer: &TransferQueue,
+        signal: UploadSignal,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        sampler: &SamplerDesc,
+        data: &[u8],
+    ) -> RendererResult<TextureId> {
+        let (sampler, generate_mipmaps) = self.prepare_texture(format, mip_levels, sampler)?;
+
+        let queue_family_transfer = (transfer.queue_family_index
+            != transfer.graphics_queue_family_index)
+            .then_some(QueueFamilyTransfer {
+                src_queue_family_index: transfer.queue_family_index,
+                dst_queue_family_index: transfer.graphics_queue_family_index,
+            });
+
+        let command_buffer = begin_one_time_commands(&self.device, transfer.command_pool)?;
+        let recorded = Texture::cmd_from_data(
+            &self.device,
+            &mut self.allocator,
+            &mut self.staging,
+            self.options.allocation_callbacks.as_ref(),
+            sampler,
+            command_buffer,
+            format,
+            width,
+            height,
+            mip_levels,
+            generate_mipmaps,
+            queue_family_transfer,
+            data,
+        );
+        let (texture, region) = match recorded {
+            Ok(recorded) => recorded,
+            Err(error) => {
+                abort_one_time_commands(&self.device, transfer.command_pool, command_buffer);
+                return Err(error);
+            }
+        };
+
+        let submitted = submit_one_time_commands(
+            &self.device,
+            transfer.queue,
+            transfer.command_pool,
+            command_buffer,
+            signal,
+            self.options.allocation_callbacks.as_ref(),
+        );
+        let fence = match submitted {
+            Ok(fence) => fence,
+            Err(error) => {
+                self.staging
+                    .free(&self.device, &mut self.allocator, region)?;
+                texture.destroy(
+                    &self.device,
+                    &mut self.allocator,
+                    self.options.allocation_callbacks.as_ref(),
+                )?;
+                return Err(error);
+            }
+        };
+        self.retired.retire_after_fence(
+            fence,
+            vec![
+                Retired::Staging(region),
+                Retired::CommandBuffer(transfer.command_pool, command_buffer),
+            ],
+        );
+
+        let texture_id = self.insert_managed_texture(texture)?;
+        if let Some(queue_family_transfer) = queue_family_transfer {
+            self.pending_acquires
+                .push((texture_id, queue_family_transfer));
+        }
+
+        Ok(texture_id)
+    }
+
+    /// Record the acquisition by the graphics queue of the textures uploaded with
+    /// [`Renderer::create_texture_with_transfer_queue`] since the last call.
+    ///
+    /// Must be recorded outside of a render pass, in a submission waiting on the signals of the
+    /// uploads and executed before any frame drawing the textures.
+    ///
+    /// # Arguments
+    ///
+    /// * `command_buffer` - The Vulkan command buffer that command will be recorded to.
+    pub fn cmd_acquire_textures(&mut self, command_buffer: vk::CommandBuffer) {
+        for (texture_id, queue_family_transfer) in self.pending_acquires.drain(..) {
+            if let Some(texture) = self.managed_textures.get(&texture_id) {
+                texture.cmd_acquire(&self.device, command_buffer, queue_family_transfer);
+            }
+        }
+    }
+
+    /// Check that textures of `format` are supported and return their sampler and whether
+    /// their mip levels are generated.
+    fn prepare_texture(
+        &mut self,
+        format: vk::Format,
+        mip_levels: u32,
+        sampler: &SamplerDesc,
+    ) -> RendererResult<(vk::Sampler, bool)> {
+        check_texture_format_support(&self.instance, self.physical_device, format)?;
+        let sampler = self.samplers.get(
+            &self.device,
+            sampler,
+            self.options.allocation_callbacks.as_ref(),
+        )?;
+
+        let generate_mipmaps = self.options.generate_mipmaps
+            && mip_levels == 1
+            && supports_linear_blit(&self.instance, self.physical_device, format);
+
+        Ok((sampler, generate_mipmaps))
+    }
+
+    /// Allocate the descriptor set of a texture owned by the renderer and return its id.
+    ///
+    /// The texture is retired if the descriptor set cannot be allocated since its upload
+    /// may already be recorded.
+    fn insert_managed_texture(&mut self, texture: Texture<A>) -> RendererResult<TextureId> {
+        let descriptor_set = match self.descriptors.allocate(
+            &self.device,
+            texture.image_view,
+            texture.sampler,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            self.options.allocation_callbacks.as_ref(),
+        ) {
+            Ok(descriptor_set) => descriptor_set,
+            Err(error) => {
+                self.retire(Retired::Texture(texture));
+                return Err(error);
+            }
+        };
+
+        let texture_id = self.textures.insert(descriptor_set);
+        self.managed_textures.insert(texture_id, texture);
+
+        Ok(texture_id)
+    }
+
+    /// Destroy a texture created with [`Renderer::create_texture`].
+    ///
+    /// It is removed from [`Renderer::textures`] immediately. The texture and its descriptor set
+    /// are released once the frames in flight that may draw it have completed, see
+    /// [`Renderer::release_retired_resources`].
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::BadTexture`] - If `texture_id` was not created with [`Renderer::create_texture`].
+    pub fn destroy_texture(&mut self, texture_id: TextureId) -> RendererResult<()> {
+        let texture = self
+            .managed_textures
+            .remove(&texture_id)
+            .ok_or(RendererError::BadTexture(texture_id))?;
+        self.pending_acquires.retain(|(id, _)| *id != texture_id);
+
+        if let Some(descriptor_set) = self.textures.remove(texture_id) {
+            self.retire(Retired::DescriptorSet(descriptor_set));
+        }
+        self.retire(Retired::Texture(texture));
+
+        Ok(())
+    }
+
+    /// Register an image owned by the application so it can be drawn by imgui and return its id.
+    ///
+    /// A descriptor set sampling `image_view` is allocated from the renderer's descriptor pools
+    /// and registered in [`Renderer::textures`]. The image is not owned by the renderer. It must
+    /// be in `image_layout` whenever a frame drawing it is executed and stay alive until it is
+    /// unregistered with [`Renderer::unregister_image`].
+    ///
+    /// # Arguments
+    ///
+    /// * `image_view` - The view of the image to draw.
+    /// * `sampler` - A sampler owned by the application, or the description of a sampler
+    ///   created by the renderer.
+    /// * `image_layout` - The layout of the image when it is sampled.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// let shadow_map_id = renderer.register_image(
+    ///     shadow_map_view,
+    ///     SamplerDesc::default(),
+    ///     vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    /// )?;
+    /// ui.image(shadow_map_id, [256.0, 256.0]);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError`] - If any Vulkan error is encountered during sampler or descriptor set creation.
+    pub fn register_image<S: Into<ImageSampler>>(
+        &mut self,
+        image_view: vk::ImageView,
+        sampler: S,
+        image_layout: vk::ImageLayout,
+    ) -> RendererResult<TextureId> {
+        let sampler = match sampler.into() {
+            ImageSampler::Sampler(sampler) => sampler,
+            ImageSampler::Desc(desc) => self.samplers.get(
+                &self.device,
+                &desc,
+                self.options.allocation_callbacks.as_ref(),
+            )?,
+        };
+
+        let descriptor_set = self.descriptors.allocate(
+            &self.device,
+            image_view,
+            sampler,
+            image_layout,
+            self.options.allocation_callbacks.as_ref(),
+        )?;
+
+        let texture_id = self.textures.insert(descriptor_set);
+        self.registered_images.insert(texture_id);
+
+        Ok(texture_id)
+    }
+
+    /// Unregister an image registered with [`Renderer::register_image`].
+    ///
+    /// It is removed from [`Renderer::textures`] immediately. Its descriptor set is released
+    /// once the frames in flight that may draw it have completed, until then the image and
+    /// its sampler must stay alive.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::BadTexture`] - If `texture_id` was not registered with [`Renderer::register_image`].
+    pub fn unregister_image(&mut self, texture_id: TextureId) -> RendererResult<()> {
+        if !self.registered_images.remove(&texture_id) {
+            return Err(RendererError::BadTexture(texture_id));
+        }
+
+        if let Some(descriptor_set) = self.textures.remove(texture_id) {
+            self.retire(Retired::DescriptorSet(descriptor_set));
+        }
+
+        Ok(())
+    }
+
+    /// Record the update of a region of a texture created with [`Renderer::create_texture`].
+    ///
+    /// The data is copied to the renderer's staging buffers and the commands copying it to the
+    /// texture are recorded into `command_buffer`. Nothing is submitted. The texture is
+    /// transitioned from SHADER_READ_ONLY_OPTIMAL to TRANSFER_DST_OPTIMAL for the copy and back.
+    /// The staging memory is recycled once the frame it was recorded in is no longer in flight.
+    ///
+    /// Only the first mip level is updated. Mip levels generated with [`Options::generate_mipmaps`]
+    /// are generated again from it. For block compressed textures the region must be aligned to
+    /// the blocks of the format. Commands must be recorded outside of a render pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `command_buffer` - The Vulkan command buffer that command will be recorded to.
+    /// * `texture_id` - The id of the texture to update.
+    /// * `x` - The horizontal offset in pixels of the region.
+    /// * `y` - The vertical offset in pixels of the region.
+    /// * `width` - The width of the region.
+    /// * `height` - The height of the region.
+    /// * `data` - The region data, tightly packed texels or blocks of the texture format.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError::BadTexture`] - If `texture_id` was not created with [`Renderer::create_texture`].
+    /// * [`RendererError::BadTextureData`] - If the region is empty, exceeds the texture, is not aligned to its blocks or `data` does not match its size.
+    /// * [`RendererError`] - If any error is encountered during command recording.
+    #[allow(clippy::too_many_arguments)]
+    #[track_caller]
+    pub fn update_texture_region(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        texture_id: TextureId,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> RendererResult<()> {
+        let texture = self
+            .managed_textures
+            .get(&texture_id)
+            .ok_or(RendererError::BadTexture(texture_id))?;
+
+        let region = texture.cmd_update_region(
+            &self.device,
+            &mut self.allocator,
+            &mut self.staging,
+            command_buffer,
+            [x, y],
+            [width, height],
+            data,
+        )?;
+        self.retire(Retired::Staging(region));
+
+        Ok(())
+    }
+
+    /// Queue a resource for destruction once the frames in flight that may use it have completed.
+    fn retire(&mut self, resource: Retired<A>) {
+        self.retired.retire(self.frame_count, resource);
+    }
+
+    /// Release the retired resources that are no longer used by a frame in flight.
+    fn release_completed_resources(&mut self) -> RendererResult<()> {
+        // Frames up to `frame_count - in_flight_frames` have completed when a new frame starts.
+        let in_flight_frames = self.options.in_flight_frames as u64;
+        let Some(completed_frame) = self.frame_count.checked_sub(in_flight_frames + 1) else {
+            return Ok(());
+        };
+        self.retired.release(
+            completed_frame,
+            &self.device,
+            &mut self.allocator,
+            &mut self.descriptors,
+            &mut self.staging,
+            self.options.allocation_callbacks.as_ref(),
+        )
+    }
+
+    /// Release all resources retired by the renderer without waiting for frames in flight.
+    ///
+    /// Textures destroyed with [`Renderer::destroy_texture`], images unregistered with
+    /// [`Renderer::unregister_image`], the previous fonts texture, resized vertex/index buffers and
+    /// staging memory are kept alive until the frames in flight that may use them have
+    /// completed. They are released as new frames are drawn. Call this function to release them
+    /// earlier, once the gpu is done with every submitted frame, for instance after waiting for the
+    /// fences of all frames in flight or for the device to be idle. Staging and command buffers
+    /// of uploads to a transfer queue are released as well, so the uploads must have completed.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError`] - If any error is encountered during resource destruction.
+    pub fn release_retired_resources(&mut self) -> RendererResult<()> {
+        self.retired.release(
+            u64::MAX,
+            &self.device,
+            &mut self.allocator,
+            &mut self.descriptors,
+            &mut self.staging,
+            self.options.allocation_callbacks.as_ref(),
+        )
+    }
+
+    /// Return the memory currently held by the renderer.
+    ///
+    /// Sizes are reported by the allocator with [`Allocate::memory_size`] and
+    /// include any padding added by the allocator.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let fonts_texture = self
+            .fonts_texture
+            .as_ref()
+            .map(|texture| texture.memory_stats(&self.allocator))
+            .unwrap_or_default();
+        let frames = self
+            .frames
+            .as_ref()
+            .map(|frames| {
+                frames
+                    .meshes
+                    .iter()
+                    .map(|mesh| mesh.memory_stats(&self.allocator))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut textures = ResourceStats::default();
+        for texture in self.managed_textures.values() {
+            textures.merge(texture.memory_stats(&self.allocator));
+        }
+
+        MemoryStats {
+            fonts_texture,
+            frames,
+            textures,
+            retired: self.retired.memory_stats(&self.allocator),
+            staging: self.staging.memory_stats(&self.allocator),
+        }
+    }
+
+    /// Return the usage of the descriptor pools the renderer allocates descriptor sets from.
+    ///
+    /// Pools are created as needed for the fonts texture, managed textures and registered
+    /// images. Descriptor sets of destroyed textures and unregistered images are reused.
+    pub fn descriptor_pool_stats(&self) -> DescriptorPoolStats {
+        self.descriptors.stats()
+    }
+
     fn lookup_descriptor_set(&self, texture_id: TextureId) -> RendererResult<vk::DescriptorSet> {
         if texture_id.id() == usize::MAX {
             Ok(self.descriptor_set)
@@ -385,16 +1389,17 @@ impl Renderer {
     /// # Arguments
     ///
     /// * `queue` - A Vulkan queue.
-    ///             It will be used to submit commands during initialization to upload
-    ///             data to the gpu. The type of queue must be supported by the following
-    ///             commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
-    ///             [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
+    ///   It will be used to submit commands during initialization to upload
+    ///   data to the gpu. The type of queue must be supported by the following
+    ///   commands: [vkCmdCopyBufferToImage](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdCopyBufferToImage.html),
+    ///   [vkCmdPipelineBarrier](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/man/html/vkCmdPipelineBarrier.html)
     /// * `command_pool` - A Vulkan command pool used to allocate command buffers to upload textures to the gpu.
     /// * `imgui` - The imgui context.
     ///
     /// # Errors
     ///
     /// * [`RendererError`] - If any error is encountered during texture update.
+    #[track_caller]
     pub fn update_fonts_texture(
         &mut self,
         queue: vk::Queue,
@@ -405,45 +1410,122 @@ impl Renderer {
         let fonts_texture = {
             let fonts = imgui.fonts();
             let atlas_texture = fonts.build_rgba32_texture();
+            let sampler = self.samplers.get(
+                &self.device,
+                &SamplerDesc::default(),
+                self.options.allocation_callbacks.as_ref(),
+            )?;
 
             Texture::from_rgba8(
                 &self.device,
                 queue,
                 command_pool,
                 &mut self.allocator,
+                &mut self.staging,
+                self.options.allocation_callbacks.as_ref(),
+                sampler,
                 atlas_texture.width,
                 atlas_texture.height,
                 atlas_texture.data,
             )?
         };
 
+        self.replace_fonts_texture(fonts_texture, imgui)
+    }
+
+    /// Record the update of the fonts texture after having added new fonts to imgui.
+    ///
+    /// Same as [`Renderer::update_fonts_texture`] but the upload is recorded into `command_buffer`
+    /// instead of being submitted and waited for. The new fonts texture is drawn by frames
+    /// submitted after `command_buffer`. The previous fonts texture is destroyed and the staging
+    /// memory recycled once the frame they were recorded in is no longer in flight. Commands must be
+    /// recorded outside of a render pass.
+    ///
+    /// # Arguments
+    ///
+    /// * `command_buffer` - The Vulkan command buffer that command will be recorded to.
+    /// * `imgui` - The imgui context.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError`] - If any error is encountered during texture update.
+    #[track_caller]
+    pub fn cmd_update_fonts_texture(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        imgui: &mut Context,
+    ) -> RendererResult<()> {
+        // Record the upload of the new fonts texture
+        let fonts_texture = {
+            let fonts = imgui.fonts();
+            let atlas_texture = fonts.build_rgba32_texture();
+            let sampler = self.samplers.get(
+                &self.device,
+                &SamplerDesc::default(),
+                self.options.allocation_callbacks.as_ref(),
+            )?;
+
+            let (texture, region) = Texture::cmd_from_data(
+                &self.device,
+                &mut self.allocator,
+                &mut self.staging,
+                self.options.allocation_callbacks.as_ref(),
+                sampler,
+                command_buffer,
+                vk::Format::R8G8B8A8_UNORM,
+                atlas_texture.width,
+                atlas_texture.height,
+                1,
+                false,
+                None,
+                atlas_texture.data,
+            )?;
+            self.retire(Retired::Staging(region));
+            texture
+        };
+
+        self.replace_fonts_texture(fonts_texture, imgui)
+    }
+
+    /// Make `fonts_texture` the fonts texture and retire the previous one.
+    fn replace_fonts_texture(
+        &mut self,
+        fonts_texture: Texture<A>,
+        imgui: &mut Context,
+    ) -> RendererResult<()> {
         let fonts = imgui.fonts();
         fonts.tex_id = TextureId::from(usize::MAX);
 
-        // Free Descriptor set the create a new one
-        let old_descriptor_set = self.descriptor_set;
-        unsafe {
-            self.device
-                .free_descriptor_sets(self.descriptor_pool, &[old_descriptor_set])?
-        };
-        self.descriptor_set = create_vulkan_descriptor_set(
+        // Create a new descriptor set, the old one may still be used by frames in flight
+        let descriptor_set = match self.descriptors.allocate(
             &self.device,
-            self.descriptor_set_layout,
-            self.descriptor_pool,
             fonts_texture.image_view,
             fonts_texture.sampler,
-        )?;
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            self.options.allocation_callbacks.as_ref(),
+        ) {
+            Ok(descriptor_set) => descriptor_set,
+            Err(error) => {
+                self.retire(Retired::Texture(fonts_texture));
+                return Err(error);
+            }
+        };
+        let old_descriptor_set = std::mem::replace(&mut self.descriptor_set, descriptor_set);
+        self.retire(Retired::DescriptorSet(old_descriptor_set));
 
-        // Free old fonts texture
-        let mut old_texture = self.fonts_texture.replace(fonts_texture);
-        if let Some(texture) = old_texture.take() {
-            texture.destroy(&self.device, &mut self.allocator)?;
+        // Retire old fonts texture
+        if let Some(texture) = self.fonts_texture.replace(fonts_texture) {
+            self.retire(Retired::Texture(texture));
         }
 
         Ok(())
     }
 
-    /// Record commands required to render the gui.RendererError.
+    /// Record commands required to upload the draw data to the gpu.
+    ///
+    /// It must be called before the render pass in which the gui is drawn when
+    /// [`Options::device_local_buffers`] is true. It is optional otherwise. The next call
+    /// to [`cmd_draw`] will draw the uploaded data.
     ///
     /// # Arguments
     ///
@@ -453,7 +1535,10 @@ impl Renderer {
     /// # Errors
     ///
     /// * [`RendererError`] - If any Vulkan error is encountered during command recording.
-    pub fn cmd_draw(
+    ///
+    /// [`cmd_draw`]: #method.cmd_draw
+    #[track_caller]
+    pub fn cmd_upload(
         &mut self,
         command_buffer: vk::CommandBuffer,
         draw_data: &DrawData,
@@ -462,17 +1547,81 @@ impl Renderer {
             return Ok(());
         }
 
+        self.update_next_mesh(draw_data)?;
+        let mesh = self.frames.as_ref().unwrap().current();
+        mesh.cmd_upload(&self.device, command_buffer, draw_data);
+        self.uploaded = true;
+
+        Ok(())
+    }
+
+    // Update the mesh of the next frame in flight with `draw_data`.
+    #[track_caller]
+    fn update_next_mesh(&mut self, draw_data: &DrawData) -> RendererResult<()> {
+        self.frame_count += 1;
+        self.release_completed_resources()?;
+
         if self.frames.is_none() {
+            let storage = MeshStorage::new(&self.options, &self.allocator);
+            log::debug!("Creating frames with {storage:?} mesh storage");
+
             self.frames.replace(Frames::new(
                 &self.device,
                 &mut self.allocator,
                 draw_data,
                 self.options.in_flight_frames,
+                &self.options.buffer_policy,
+                storage,
             )?);
         }
 
         let mesh = self.frames.as_mut().unwrap().next();
-        mesh.update(&self.device, &mut self.allocator, draw_data)?;
+        let replaced = mesh.update(
+            &self.device,
+            &mut self.allocator,
+            draw_data,
+            &self.options.buffer_policy,
+        )?;
+        if let Some(replaced) = replaced {
+            self.retire(Retired::Mesh(replaced));
+        }
+
+        Ok(())
+    }
+
+    /// Record commands required to render the gui.RendererError.
+    ///
+    /// # Arguments
+    ///
+    /// * `command_buffer` - The Vulkan command buffer that command will be recorded to.
+    /// * `draw_data` - A reference to the imgui `DrawData` containing rendering data.
+    ///
+    /// # Errors
+    ///
+    /// * [`RendererError`] - If any Vulkan error is encountered during command recording.
+    /// * [`RendererError::MissingUpload`] - If [`Options::device_local_buffers`] is true and
+    ///   [`cmd_upload`] was not called first.
+    ///
+    /// [`cmd_upload`]: #method.cmd_upload
+    #[track_caller]
+    pub fn cmd_draw(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        draw_data: &DrawData,
+    ) -> RendererResult<()> {
+        if draw_data.total_vtx_count == 0 {
+            return Ok(());
+        }
+
+        if !std::mem::take(&mut self.uploaded) {
+            if self.options.device_local_buffers {
+                return Err(RendererError::MissingUpload);
+            }
+            self.update_next_mesh(draw_data)?;
+        }
+
+        let mesh = self.frames.as_ref().unwrap().current();
+        let (buffer, index_offset) = (mesh.buffer(), mesh.index_offset());
 
         unsafe {
             self.device.cmd_bind_pipeline(
@@ -516,15 +1665,15 @@ impl Renderer {
         unsafe {
             self.device.cmd_bind_index_buffer(
                 command_buffer,
-                mesh.indices,
-                0,
+                buffer,
+                index_offset,
                 vk::IndexType::UINT16,
             )
         };
 
         unsafe {
             self.device
-                .cmd_bind_vertex_buffers(command_buffer, 0, &[mesh.vertices], &[0])
+                .cmd_bind_vertex_buffers(command_buffer, 0, &[buffer], &[0])
         };
 
         let mut index_offset = 0;
@@ -607,10 +1756,11 @@ impl Renderer {
     }
 }
 
-impl Drop for Renderer {
+impl<A: Allocate> Drop for Renderer<A> {
     fn drop(&mut self) {
         log::debug!("Destroying ImGui Renderer");
         let device = &self.device;
+        let allocation_callbacks = self.options.allocation_callbacks.as_ref();
 
         unsafe {
             if let Some(frames) = self.frames.take() {
@@ -618,36 +1768,63 @@ impl Drop for Renderer {
                     .destroy(device, &mut self.allocator)
                     .expect("Failed to destroy frame data");
             }
-            device.destroy_pipeline(self.pipeline, None);
-            device.destroy_pipeline_layout(self.pipeline_layout, None);
-            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.retired
+                .release(
+                    u64::MAX,
+                    device,
+                    &mut self.allocator,
+                    &mut self.descriptors,
+                    &mut self.staging,
+                    allocation_callbacks,
+                )
+                .expect("Failed to destroy retired resources");
+            self.staging
+                .destroy(device, &mut self.allocator)
+                .expect("Failed to destroy staging buffers");
+            device.destroy_pipeline(self.pipeline, allocation_callbacks);
+            device.destroy_pipeline_layout(self.pipeline_layout, allocation_callbacks);
+            self.descriptors.destroy(device, allocation_callbacks);
             self.fonts_texture
                 .take()
                 .unwrap()
-                .destroy(device, &mut self.allocator)
+                .destroy(device, &mut self.allocator, allocation_callbacks)
                 .expect("Failed to fronts data");
-            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            for (_, texture) in self.managed_textures.drain() {
+                texture
+                    .destroy(device, &mut self.allocator, allocation_callbacks)
+                    .expect("Failed to destroy texture");
+            }
+            self.samplers.destroy(device, allocation_callbacks);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, allocation_callbacks);
+            self.allocator
+                .free_unused_memory(device)
+                .expect("Failed to free unused memory");
         }
     }
 }
 
 // Structure holding data for all frames in flight.
-struct Frames {
+struct Frames<A: Allocate> {
     index: usize,
     count: usize,
-    meshes: Vec<Mesh>,
+    meshes: Vec<Mesh<A>>,
 }
 
-impl Frames {
+impl<A: Allocate> Frames<A> {
+    #[track_caller]
     fn new(
         device: &Device,
-        allocator: &mut Allocator,
+        allocator: &mut A,
         draw_data: &DrawData,
         count: usize,
+        policy: &BufferPolicy,
+        storage: MeshStorage,
     ) -> RendererResult<Self> {
-        let meshes = (0..count)
-            .map(|_| Mesh::new(device, allocator, draw_data))
-            .collect::<Result<Vec<_>, _>>()?;
+        // A loop rather than an iterator so the caller location reaches the allocator.
+        let mut meshes = Vec::with_capacity(count);
+        for _ in 0..count {
+            meshes.push(Mesh::new(device, allocator, draw_data, policy, storage)?);
+        }
         Ok(Self {
             index: 0,
             count,
@@ -655,13 +1832,18 @@ impl Frames {
         })
     }
 
-    fn next(&mut self) -> &mut Mesh {
+    fn next(&mut self) -> &mut Mesh<A> {
         let result = &mut self.meshes[self.index];
         self.index = (self.index + 1) % self.count;
         result
     }
 
-    fn destroy(self, device: &Device, allocator: &mut Allocator) -> RendererResult<()> {
+    // Mesh last returned by `next`.
+    fn current(&self) -> &Mesh<A> {
+        &self.meshes[(self.index + self.count - 1) % self.count]
+    }
+
+    fn destroy(self, device: &Device, allocator: &mut A) -> RendererResult<()> {
         for mesh in self.meshes.into_iter() {
             mesh.destroy(device, allocator)?;
         }
@@ -671,131 +1853,386 @@ impl Frames {
 
 mod mesh {
 
-    use super::allocator::{Allocate, Allocator, Memory};
-    use super::vulkan::*;
-    use crate::RendererResult;
+    use super::allocator::{Allocate, MemoryLocation};
+    use super::{BufferPolicy, Options};
+    use crate::{MeshStats, RendererResult, ResourceStats};
     use ash::{vk, Device};
     use imgui::{DrawData, DrawVert};
     use std::mem::size_of;
 
+    /// How vertex and index data reach the gpu.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum MeshStorage {
+        /// Host visible buffers written directly.
+        Host,
+        /// Device local and host visible buffers written directly.
+        DeviceMapped,
+        /// Device local buffers copied from host visible staging buffers.
+        DeviceStaged,
+    }
+
+    impl MeshStorage {
+        pub fn new<A: Allocate>(options: &Options, allocator: &A) -> Self {
+            if !options.device_local_buffers {
+                MeshStorage::Host
+            } else if allocator.supports_host_visible_device_memory() {
+                MeshStorage::DeviceMapped
+            } else {
+                MeshStorage::DeviceStaged
+            }
+        }
+    }
+
     /// Vertex and index buffer resources for one frame in flight.
-    pub struct Mesh {
-        pub vertices: vk::Buffer,
-        vertices_mem: Memory,
+    ///
+    /// Vertices and indices share a single buffer. Indices are stored after the vertices.
+    /// The buffer has a staging buffer of the same size when it is not host visible.
+    pub struct Mesh<A: Allocate> {
+        buffer: vk::Buffer,
+        memory: A::Memory,
+        staging: Option<(vk::Buffer, A::Memory)>,
+        storage: MeshStorage,
         vertex_count: usize,
-        pub indices: vk::Buffer,
-        indices_mem: Memory,
+        vertex_underused_frames: u32,
         index_count: usize,
+        index_underused_frames: u32,
+        used_vertex_count: usize,
+        used_index_count: usize,
     }
 
-    impl Mesh {
+    impl<A: Allocate> Mesh<A> {
+        #[track_caller]
         pub fn new(
             device: &Device,
-            allocator: &mut Allocator,
+            allocator: &mut A,
             draw_data: &DrawData,
+            policy: &BufferPolicy,
+            storage: MeshStorage,
         ) -> RendererResult<Self> {
-            let vertices = create_vertices(draw_data);
-            let vertex_count = vertices.len();
-            let indices = create_indices(draw_data);
-            let index_count = indices.len();
+            let vertex_count =
+                policy.capacity(draw_data.total_vtx_count as _, policy.min_vertex_count);
+            let index_count =
+                policy.capacity(draw_data.total_idx_count as _, policy.min_index_count);
 
-            // Create a vertex buffer
-            let (vertices, vertices_mem) = create_and_fill_buffer(
-                device,
-                allocator,
-                &vertices,
-                vk::BufferUsageFlags::VERTEX_BUFFER,
-            )?;
+            Self::with_capacity(device, allocator, storage, vertex_count, index_count)
+        }
 
-            // Create an index buffer
-            let (indices, indices_mem) = create_and_fill_buffer(
-                device,
-                allocator,
-                &indices,
-                vk::BufferUsageFlags::INDEX_BUFFER,
-            )?;
+        #[track_caller]
+        fn with_capacity(
+            device: &Device,
+            allocator: &mut A,
+            storage: MeshStorage,
+            vertex_count: usize,
+            index_count: usize,
+        ) -> RendererResult<Self> {
+            let size = index_offset(vertex_count) + index_count * size_of::<u16>();
+            let usage = vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER;
+
+            let (buffer, memory, staging) = match storage {
+                MeshStorage::Host => {
+                    let (buffer, memory) =
+                        allocator.create_buffer(device, size, usage, MemoryLocation::CpuToGpu)?;
+                    (buffer, memory, None)
+                }
+                MeshStorage::DeviceMapped => {
+                    let (buffer, memory) =
+                        allocator.create_buffer(device, size, usage, MemoryLocation::GpuMapped)?;
+                    (buffer, memory, None)
+                }
+                MeshStorage::DeviceStaged => {
+                    let (buffer, memory) = allocator.create_buffer(
+                        device,
+                        size,
+                        usage | vk::BufferUsageFlags::TRANSFER_DST,
+                        MemoryLocation::GpuOnly,
+                    )?;
+                    let staging = allocator.create_buffer(
+                        device,
+                        size,
+                        vk::BufferUsageFlags::TRANSFER_SRC,
+                        MemoryLocation::CpuToGpu,
+                    )?;
+                    (buffer, memory, Some(staging))
+                }
+            };
 
-            Ok(Mesh {
-                vertices,
-                vertices_mem,
+            Ok(Self {
+                buffer,
+                memory,
+                staging,
+                storage,
                 vertex_count,
-                indices,
-                indices_mem,
+                vertex_underused_frames: 0,
                 index_count,
+                index_underused_frames: 0,
+                used_vertex_count: 0,
+                used_index_count: 0,
             })
         }
 
+        pub fn buffer(&self) -> vk::Buffer {
+            self.buffer
+        }
+
+        pub fn index_offset(&self) -> vk::DeviceSize {
+            index_offset(self.vertex_count) as _
+        }
+
+        /// Copy the draw lists of `draw_data` straight into the mapped buffer,
+        /// or its staging buffer.
+        ///
+        /// The buffer is resized first if `policy` requires it. The replaced buffers are returned
+        /// as a mesh to destroy once the frames using them have completed.
+        #[track_caller]
         pub fn update(
             &mut self,
             device: &Device,
-            allocator: &mut Allocator,
+            allocator: &mut A,
             draw_data: &DrawData,
-        ) -> RendererResult<()> {
-            let vertices = create_vertices(draw_data);
-            if draw_data.total_vtx_count as usize > self.vertex_count {
-                log::trace!("Resizing vertex buffers");
+            policy: &BufferPolicy,
+        ) -> RendererResult<Option<Self>> {
+            let vertex_count = policy.resize(
+                self.vertex_count,
+                draw_data.total_vtx_count as _,
+                policy.min_vertex_count,
+                &mut self.vertex_underused_frames,
+            );
+            let index_count = policy.resize(
+                self.index_count,
+                draw_data.total_idx_count as _,
+                policy.min_index_count,
+                &mut self.index_underused_frames,
+            );
+            let mut replaced = None;
+            if vertex_count.is_some() || index_count.is_some() {
+                let vertex_count = vertex_count.unwrap_or(self.vertex_count);
+                let index_count = index_count.unwrap_or(self.index_count);
+                log::trace!(
+                    "Resizing mesh buffer to {vertex_count} vertices and {index_count} indices"
+                );
+
+                let mesh = Self::with_capacity(
+                    device,
+                    allocator,
+                    self.storage,
+                    vertex_count,
+                    index_count,
+                )?;
+                replaced = Some(std::mem::replace(self, mesh));
+            }
 
-                let vertex_count = vertices.len();
-                let size = vertex_count * size_of::<DrawVert>();
-                let (vertices, vertices_mem) =
-                    allocator.create_buffer(device, size, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+            self.used_vertex_count = draw_data.total_vtx_count as _;
+            self.used_index_count = draw_data.total_idx_count as _;
 
-                self.vertex_count = vertex_count;
+            let index_offset = index_offset(self.vertex_count);
+            let memory = match self.staging.as_mut() {
+                Some((_, staging_mem)) => staging_mem,
+                None => &mut self.memory,
+            };
+            allocator.update_buffer(
+                device,
+                memory,
+                0,
+                draw_data
+                    .draw_lists()
+                    .map(|draw_list| draw_list.vtx_buffer()),
+            )?;
+            allocator.update_buffer(
+                device,
+                memory,
+                index_offset,
+                draw_data
+                    .draw_lists()
+                    .map(|draw_list| draw_list.idx_buffer()),
+            )?;
 
-                let old_vertices = self.vertices;
-                self.vertices = vertices;
+            Ok(replaced)
+        }
 
-                let old_vertices_mem = std::mem::replace(&mut self.vertices_mem, vertices_mem);
+        /// Record the copy of the staging buffer to the device local buffer.
+        ///
+        /// Does nothing if the buffer is host visible.
+        pub fn cmd_upload(
+            &self,
+            device: &Device,
+            command_buffer: vk::CommandBuffer,
+            draw_data: &DrawData,
+        ) {
+            let Some((staging, _)) = self.staging.as_ref() else {
+                return;
+            };
+
+            let index_offset = index_offset(self.vertex_count) as vk::DeviceSize;
+            let regions = [
+                vk::BufferCopy::default()
+                    .size((draw_data.total_vtx_count as usize * size_of::<DrawVert>()) as _),
+                vk::BufferCopy::default()
+                    .src_offset(index_offset)
+                    .dst_offset(index_offset)
+                    .size((draw_data.total_idx_count as usize * size_of::<u16>()) as _),
+            ];
+            unsafe { device.cmd_copy_buffer(command_buffer, *staging, self.buffer, &regions) };
+
+            let barrier = vk::BufferMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(
+                    vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ,
+                )
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .buffer(self.buffer)
+                .offset(0)
+                .size(vk::WHOLE_SIZE);
+
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::VERTEX_INPUT,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[barrier],
+                    &[],
+                )
+            };
+        }
 
-                allocator.destroy_buffer(device, old_vertices, old_vertices_mem)?;
+        pub fn memory_stats(&self, allocator: &A) -> MeshStats {
+            let mut memory = ResourceStats::default();
+            memory.add_allocation(allocator.memory_size(&self.memory));
+            if let Some((_, staging_mem)) = self.staging.as_ref() {
+                memory.add_allocation(allocator.memory_size(staging_mem));
             }
-            allocator.update_buffer(device, &mut self.vertices_mem, &vertices)?;
 
-            let indices = create_indices(draw_data);
-            if draw_data.total_idx_count as usize > self.index_count {
-                log::trace!("Resizing index buffers");
+            MeshStats {
+                memory,
+                vertex_capacity: self.vertex_count,
+                vertex_count: self.used_vertex_count,
+                index_capacity: self.index_count,
+                index_count: self.used_index_count,
+            }
+        }
 
-                let index_count = indices.len();
-                let size = index_count * size_of::<u16>();
-                let (indices, indices_mem) =
-                    allocator.create_buffer(device, size, vk::BufferUsageFlags::INDEX_BUFFER)?;
+        pub fn destroy(self, device: &Device, allocator: &mut A) -> RendererResult<()> {
+            allocator.destroy_buffer(device, self.buffer, self.memory)?;
+            if let Some((staging, staging_mem)) = self.staging {
+                allocator.destroy_buffer(device, staging, staging_mem)?;
+            }
+            Ok(())
+        }
+    }
 
-                self.index_count = index_count;
+    /// Offset in bytes of the indices in a buffer holding `vertex_count` vertices.
+    ///
+    /// Aligned to 4 bytes which satisfies the index buffer offset requirements.
+    fn index_offset(vertex_count: usize) -> usize {
+        (vertex_count * size_of::<DrawVert>()).next_multiple_of(4)
+    }
+}
 
-                let old_indices = self.indices;
-                self.indices = indices;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> BufferPolicy {
+        BufferPolicy {
+            growth_factor: 1.5,
+            min_vertex_count: 100,
+            min_index_count: 200,
+            shrink_threshold: 0.25,
+            shrink_after_frames: 3,
+        }
+    }
 
-                let old_indices_mem = std::mem::replace(&mut self.indices_mem, indices_mem);
+    #[test]
+    fn capacity_has_growth_headroom() {
+        let policy = policy();
+        assert_eq!(policy.capacity(1000, 100), 1500);
+        assert_eq!(policy.capacity(1001, 100), 1502);
 
-                allocator.destroy_buffer(device, old_indices, old_indices_mem)?;
-            }
-            allocator.update_buffer(device, &mut self.indices_mem, &indices)?;
+        let no_growth = BufferPolicy {
+            growth_factor: 0.5,
+            ..policy
+        };
+        assert_eq!(no_growth.capacity(1000, 100), 1000);
+    }
 
-            Ok(())
-        }
+    #[test]
+    fn capacity_is_floored_to_min_count() {
+        let policy = policy();
+        assert_eq!(policy.capacity(0, policy.min_vertex_count), 100);
+        assert_eq!(policy.capacity(10, policy.min_index_count), 200);
+        assert_eq!(policy.capacity(200, policy.min_index_count), 300);
+    }
 
-        pub fn destroy(self, device: &Device, allocator: &mut Allocator) -> RendererResult<()> {
-            allocator.destroy_buffer(device, self.vertices, self.vertices_mem)?;
-            allocator.destroy_buffer(device, self.indices, self.indices_mem)?;
-            Ok(())
-        }
+    #[test]
+    fn resize_grows_when_count_exceeds_capacity() {
+        let policy = policy();
+        let mut underused_frames = 2;
+        assert_eq!(policy.resize(1000, 1000, 100, &mut underused_frames), None);
+        assert_eq!(
+            policy.resize(1000, 1001, 100, &mut underused_frames),
+            Some(1502)
+        );
+        assert_eq!(underused_frames, 0);
     }
 
-    fn create_vertices(draw_data: &DrawData) -> Vec<DrawVert> {
-        let vertex_count = draw_data.total_vtx_count as usize;
-        let mut vertices = Vec::with_capacity(vertex_count);
-        for draw_list in draw_data.draw_lists() {
-            vertices.extend_from_slice(draw_list.vtx_buffer());
+    #[test]
+    fn resize_shrinks_after_underused_frames() {
+        let policy = policy();
+        let mut underused_frames = 0;
+        assert_eq!(policy.resize(1000, 10, 100, &mut underused_frames), None);
+        assert_eq!(policy.resize(1000, 10, 100, &mut underused_frames), None);
+        // Shrunk to the floor rather than to the headroom of 10 elements.
+        assert_eq!(
+            policy.resize(1000, 10, 100, &mut underused_frames),
+            Some(100)
+        );
+        assert_eq!(underused_frames, 0);
+    }
+
+    #[test]
+    fn resize_resets_shrink_counter_when_usage_rises() {
+        let policy = policy();
+        let mut underused_frames = 0;
+        policy.resize(1000, 10, 100, &mut underused_frames);
+        policy.resize(1000, 10, 100, &mut underused_frames);
+        assert_eq!(underused_frames, 2);
+
+        assert_eq!(policy.resize(1000, 500, 100, &mut underused_frames), None);
+        assert_eq!(underused_frames, 0);
+        assert_eq!(policy.resize(1000, 10, 100, &mut underused_frames), None);
+        assert_eq!(policy.resize(1000, 10, 100, &mut underused_frames), None);
+        assert_eq!(
+            policy.resize(1000, 10, 100, &mut underused_frames),
+            Some(100)
+        );
+    }
+
+    #[test]
+    fn resize_never_shrinks_below_min_count() {
+        let policy = policy();
+        let mut underused_frames = 0;
+        for _ in 0..10 {
+            assert_eq!(policy.resize(100, 0, 100, &mut underused_frames), None);
         }
-        vertices
+        assert_eq!(underused_frames, 0);
     }
 
-    fn create_indices(draw_data: &DrawData) -> Vec<u16> {
-        let index_count = draw_data.total_idx_count as usize;
-        let mut indices = Vec::with_capacity(index_count);
-        for draw_list in draw_data.draw_lists() {
-            indices.extend_from_slice(draw_list.idx_buffer());
+    #[test]
+    fn resize_without_shrinking() {
+        let policy = BufferPolicy {
+            shrink_after_frames: 0,
+            ..policy()
+        };
+        let mut underused_frames = 0;
+        for _ in 0..10 {
+            assert_eq!(policy.resize(1000, 10, 100, &mut underused_frames), None);
         }
-        indices
+        assert_eq!(underused_frames, 0);
+        assert_eq!(
+            policy.resize(1000, 2000, 100, &mut underused_frames),
+            Some(3000)
+        );
     }
 }
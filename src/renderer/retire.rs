@@ -0,0 +1,147 @@
+use super::{
+    allocator::Allocate,
+    descriptor::DescriptorAllocator,
+    mesh::Mesh,
+    staging::{StagingPool, StagingRegion},
+    stats::ResourceStats,
+    vulkan::Texture,
+};
+use crate::RendererResult;
+use ash::{vk, Device};
+use std::collections::VecDeque;
+
+/// A resource that is no longer used by the renderer but may still be used by frames in flight.
+pub(crate) enum Retired<A: Allocate> {
+    Staging(StagingRegion),
+    Mesh(Mesh<A>),
+    Texture(Texture<A>),
+    DescriptorSet(vk::DescriptorSet),
+    CommandBuffer(vk::CommandPool, vk::CommandBuffer),
+}
+
+/// Resources waiting for the frames that may use them to complete before being released.
+///
+/// Resources are recorded with the frame they were retired at. Frames are counted by the
+/// renderer and always increase so the queue is ordered by frame. Resources used by submissions
+/// outside of the renderer's frames are instead recorded with a fence signaled when they complete.
+pub(crate) struct RetirementQueue<A: Allocate> {
+    resources: VecDeque<(u64, Retired<A>)>,
+    fenced_resources: Vec<(vk::Fence, Vec<Retired<A>>)>,
+}
+
+impl<A: Allocate> RetirementQueue<A> {
+    pub fn new() -> Self {
+        Self {
+            resources: VecDeque::new(),
+            fenced_resources: Vec::new(),
+        }
+    }
+
+    pub fn retire(&mut self, frame: u64, resource: Retired<A>) {
+        self.resources.push_back((frame, resource));
+    }
+
+    /// Retire resources until `fence` is signaled. The fence is owned by the queue.
+    pub fn retire_after_fence(&mut self, fence: vk::Fence, resources: Vec<Retired<A>>) {
+        self.fenced_resources.push((fence, resources));
+    }
+
+    /// Release the resources retired at or before `completed_frame` and those whose fence
+    /// is signaled. Fenced resources are all released if `completed_frame` is `u64::MAX`.
+    ///
+    /// Meshes and textures are destroyed. Descriptor sets and staging regions are returned to
+    /// `descriptors` and `staging`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn release(
+        &mut self,
+        completed_frame: u64,
+        device: &Device,
+        allocator: &mut A,
+        descriptors: &mut DescriptorAllocator,
+        staging: &mut StagingPool<A>,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> RendererResult<()> {
+        while self
+            .resources
+            .front()
+            .is_some_and(|(frame, _)| *frame <= completed_frame)
+        {
+            let (_, resource) = self.resources.pop_front().unwrap();
+            release_resource(
+                resource,
+                device,
+                allocator,
+                descriptors,
+                staging,
+                allocation_callbacks,
+            )?;
+        }
+
+        let mut index = 0;
+        while index < self.fenced_resources.len() {
+            let fence = self.fenced_resources[index].0;
+            let signaled =
+                completed_frame == u64::MAX || unsafe { device.get_fence_status(fence)? };
+            if !signaled {
+                index += 1;
+                continue;
+            }
+
+            let (fence, resources) = self.fenced_resources.swap_remove(index);
+            unsafe { device.destroy_fence(fence, allocation_callbacks) };
+            for resource in resources {
+                release_resource(
+                    resource,
+                    device,
+                    allocator,
+                    descriptors,
+                    staging,
+                    allocation_callbacks,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the memory held by the retired resources.
+    pub fn memory_stats(&self, allocator: &A) -> ResourceStats {
+        let mut stats = ResourceStats::default();
+        let fenced_resources = self
+            .fenced_resources
+            .iter()
+            .flat_map(|(_, resources)| resources);
+        for resource in self
+            .resources
+            .iter()
+            .map(|(_, r)| r)
+            .chain(fenced_resources)
+        {
+            match resource {
+                Retired::Mesh(mesh) => stats.merge(mesh.memory_stats(allocator).memory),
+                Retired::Texture(texture) => stats.merge(texture.memory_stats(allocator)),
+                Retired::Staging(_) | Retired::DescriptorSet(_) | Retired::CommandBuffer(..) => {}
+            }
+        }
+        stats
+    }
+}
+
+fn release_resource<A: Allocate>(
+    resource: Retired<A>,
+    device: &Device,
+    allocator: &mut A,
+    descriptors: &mut DescriptorAllocator,
+    staging: &mut StagingPool<A>,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) -> RendererResult<()> {
+    match resource {
+        Retired::Staging(region) => staging.free(device, allocator, region)?,
+        Retired::Mesh(mesh) => mesh.destroy(device, allocator)?,
+        Retired::Texture(texture) => texture.destroy(device, allocator, allocation_callbacks)?,
+        Retired::DescriptorSet(set) => descriptors.free(set),
+        Retired::CommandBuffer(pool, command_buffer) => unsafe {
+            device.free_command_buffers(pool, &[command_buffer])
+        },
+    }
+    Ok(())
+}
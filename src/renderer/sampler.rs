@@ -0,0 +1,199 @@
+use crate::RendererResult;
+use ash::{vk, Device, Instance};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+/// Description of the sampler of a texture created by the renderer.
+///
+/// The default description matches the sampler of the fonts texture: linear filtering and
+/// repeat addressing.
+///
+/// Samplers do not limit the sampled mip levels, their maximum LOD is
+/// [`vk::LOD_CLAMP_NONE`]. LODs are clamped to the levels of the image view instead, which for
+/// textures created by the renderer are all the levels of the image, generated or not.
+///
+/// # Example
+///
+/// ```ignore
+/// // Sampler for pixel art sprites that must not bleed on their edges.
+/// let sampler = SamplerDesc {
+///     mag_filter: vk::Filter::NEAREST,
+///     min_filter: vk::Filter::NEAREST,
+///     address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+///     address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct SamplerDesc {
+    /// Filter applied when the texture is magnified.
+    pub mag_filter: vk::Filter,
+    /// Filter applied when the texture is minified.
+    pub min_filter: vk::Filter,
+    /// Filter applied between mip levels.
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// Addressing mode of horizontal coordinates outside of \[0, 1\].
+    pub address_mode_u: vk::SamplerAddressMode,
+    /// Addressing mode of vertical coordinates outside of \[0, 1\].
+    pub address_mode_v: vk::SamplerAddressMode,
+    /// Maximum anisotropy of the sampler, or `None` to disable anisotropic filtering.
+    ///
+    /// The value is clamped to the `maxSamplerAnisotropy` limit of the device. If the physical
+    /// device does not support the `samplerAnisotropy` feature, anisotropic filtering is disabled
+    /// instead. When it is supported, the feature must be enabled when creating the device.
+    pub max_anisotropy: Option<f32>,
+    /// Color of the border when an address mode is `CLAMP_TO_BORDER`.
+    pub border_color: vk::BorderColor,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: None,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// Fields of the description compared when looking up the sampler cache.
+    #[allow(clippy::type_complexity)]
+    fn key(
+        &self,
+    ) -> (
+        vk::Filter,
+        vk::Filter,
+        vk::SamplerMipmapMode,
+        vk::SamplerAddressMode,
+        vk::SamplerAddressMode,
+        Option<u32>,
+        vk::BorderColor,
+    ) {
+        (
+            self.mag_filter,
+            self.min_filter,
+            self.mipmap_mode,
+            self.address_mode_u,
+            self.address_mode_v,
+            self.max_anisotropy.map(f32::to_bits),
+            self.border_color,
+        )
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Sampler of an image registered with [`Renderer::register_image`](crate::Renderer::register_image).
+#[derive(Debug, Clone, Copy)]
+pub enum ImageSampler {
+    /// A sampler owned by the application. It must outlive the registration of the image.
+    Sampler(vk::Sampler),
+    /// A sampler created and owned by the renderer, shared with its textures.
+    Desc(SamplerDesc),
+}
+
+impl From<vk::Sampler> for ImageSampler {
+    fn from(sampler: vk::Sampler) -> Self {
+        Self::Sampler(sampler)
+    }
+}
+
+impl From<SamplerDesc> for ImageSampler {
+    fn from(desc: SamplerDesc) -> Self {
+        Self::Desc(desc)
+    }
+}
+
+/// Samplers created by the renderer, shared by all textures with the same description.
+///
+/// Samplers live until the cache is destroyed along with the renderer.
+pub(crate) struct SamplerCache {
+    samplers: HashMap<SamplerDesc, vk::Sampler>,
+    /// Limit of the anisotropy, or `None` if the device does not support anisotropic filtering.
+    max_anisotropy: Option<f32>,
+}
+
+impl SamplerCache {
+    pub fn new(instance: &Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let features = unsafe { instance.get_physical_device_features(physical_device) };
+        Self {
+            samplers: HashMap::new(),
+            max_anisotropy: (features.sampler_anisotropy == vk::TRUE)
+                .then_some(properties.limits.max_sampler_anisotropy),
+        }
+    }
+
+    /// Return the sampler matching `desc`, creating it if needed.
+    pub fn get(
+        &mut self,
+        device: &Device,
+        desc: &SamplerDesc,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> RendererResult<vk::Sampler> {
+        if let Some(sampler) = self.samplers.get(desc) {
+            return Ok(*sampler);
+        }
+
+        log::debug!("Creating sampler {desc:?}");
+        if desc.max_anisotropy.is_some() && self.max_anisotropy.is_none() {
+            log::warn!("Anisotropic filtering is disabled, samplerAnisotropy is not supported");
+        }
+        let anisotropy = desc
+            .max_anisotropy
+            .zip(self.max_anisotropy)
+            .map(|(anisotropy, max_anisotropy)| anisotropy.clamp(1.0, max_anisotropy));
+        // Lod is bounded by the level count of each image view so samplers can be shared
+        // between textures with different mip chains.
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(desc.mag_filter)
+            .min_filter(desc.min_filter)
+            .address_mode_u(desc.address_mode_u)
+            .address_mode_v(desc.address_mode_v)
+            .address_mode_w(vk::SamplerAddressMode::REPEAT)
+            .anisotropy_enable(anisotropy.is_some())
+            .max_anisotropy(anisotropy.unwrap_or(1.0))
+            .border_color(desc.border_color)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(desc.mipmap_mode)
+            .mip_lod_bias(0.0)
+            .min_lod(0.0)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = unsafe { device.create_sampler(&sampler_info, allocation_callbacks)? };
+
+        self.samplers.insert(*desc, sampler);
+        Ok(sampler)
+    }
+
+    /// Destroy all samplers. Textures using them must be destroyed already.
+    pub fn destroy(
+        &mut self,
+        device: &Device,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) {
+        for (_, sampler) in self.samplers.drain() {
+            unsafe { device.destroy_sampler(sampler, allocation_callbacks) };
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use super::{
+    allocator::{Allocate, MemoryLocation},
+    stats::ResourceStats,
+};
+use crate::RendererResult;
+use ash::{vk, Device};
+
+/// Size in bytes of the staging buffers shared by uploads.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+/// Alignment of the regions, a multiple of the texel and block sizes of all texture formats.
+const REGION_ALIGNMENT: usize = 16;
+
+/// Region of a staging buffer holding the data of an upload.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StagingRegion {
+    chunk: usize,
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+}
+
+struct Chunk<A: Allocate> {
+    buffer: vk::Buffer,
+    memory: A::Memory,
+    size: usize,
+    /// End of the last region allocated from the chunk.
+    head: usize,
+    /// Number of regions not freed yet.
+    regions: usize,
+}
+
+impl<A: Allocate> Chunk<A> {
+    /// Return true if a region of `size` bytes can be allocated after the last one.
+    fn fits(&self, size: usize) -> bool {
+        self.head.next_multiple_of(REGION_ALIGNMENT) + size <= self.size
+    }
+}
+
+/// Host visible buffers the data of all uploads is copied to before being copied to images.
+///
+/// Regions are allocated one after the other from the current chunk. When it is full, another
+/// chunk with enough room is used or a new one is created. The space of a chunk is recycled
+/// once all its regions are freed, which the renderer does when the commands reading them have
+/// completed. Chunks are [`CHUNK_SIZE`] bytes, except for uploads that do not fit, which get a
+/// chunk of their own destroyed once it is freed.
+pub(crate) struct StagingPool<A: Allocate> {
+    chunks: Vec<Option<Chunk<A>>>,
+    current: Option<usize>,
+}
+
+impl<A: Allocate> StagingPool<A> {
+    pub fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// Copy `data` to a region of a staging buffer.
+    ///
+    /// The region must be freed with [`StagingPool::free`] once the gpu is done reading it.
+    #[track_caller]
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        allocator: &mut A,
+        data: &[u8],
+    ) -> RendererResult<StagingRegion> {
+        self.allocate_slices(device, allocator, &[data])
+    }
+
+    /// Copy `slices` one after the other to a region of a staging buffer.
+    ///
+    /// The region must be freed with [`StagingPool::free`] once the gpu is done reading it.
+    #[track_caller]
+    pub fn allocate_slices(
+        &mut self,
+        device: &Device,
+        allocator: &mut A,
+        slices: &[&[u8]],
+    ) -> RendererResult<StagingRegion> {
+        let size = slices.iter().map(|slice| slice.len()).sum();
+        let current = self
+            .current
+            .filter(|&index| self.chunks[index].as_ref().unwrap().fits(size));
+        let index = match current {
+            Some(index) => index,
+            None => self.chunk_with_space(device, allocator, size)?,
+        };
+
+        let chunk = self.chunks[index].as_mut().unwrap();
+        // Chunks dedicated to large uploads are not shared so they can be destroyed early.
+        if chunk.size <= CHUNK_SIZE {
+            self.current = Some(index);
+        }
+        let offset = chunk.head.next_multiple_of(REGION_ALIGNMENT);
+        allocator.update_buffer(device, &mut chunk.memory, offset, slices.iter().copied())?;
+        chunk.head = offset + size;
+        chunk.regions += 1;
+
+        Ok(StagingRegion {
+            chunk: index,
+            buffer: chunk.buffer,
+            offset: offset as _,
+        })
+    }
+
+    /// Return the index of a chunk with room for `size` bytes, creating it if needed.
+    #[track_caller]
+    fn chunk_with_space(
+        &mut self,
+        device: &Device,
+        allocator: &mut A,
+        size: usize,
+    ) -> RendererResult<usize> {
+        let chunk_with_space = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.as_ref().is_some_and(|chunk| chunk.fits(size)));
+        if let Some(index) = chunk_with_space {
+            return Ok(index);
+        }
+
+        let size = size.max(CHUNK_SIZE);
+        log::debug!("Creating staging buffer of {size} bytes");
+        let (buffer, memory) = allocator.create_buffer(
+            device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )?;
+        let chunk = Chunk {
+            buffer,
+            memory,
+            size,
+            head: 0,
+            regions: 0,
+        };
+
+        match self.chunks.iter().position(Option::is_none) {
+            Some(index) => {
+                self.chunks[index] = Some(chunk);
+                Ok(index)
+            }
+            None => {
+                self.chunks.push(Some(chunk));
+                Ok(self.chunks.len() - 1)
+            }
+        }
+    }
+
+    /// Free a region so its space can be reused.
+    pub fn free(
+        &mut self,
+        device: &Device,
+        allocator: &mut A,
+        region: StagingRegion,
+    ) -> RendererResult<()> {
+        let Some(chunk) = self.chunks[region.chunk].as_mut() else {
+            return Ok(());
+        };
+        chunk.regions -= 1;
+        if chunk.regions > 0 {
+            return Ok(());
+        }
+
+        chunk.head = 0;
+        if chunk.size > CHUNK_SIZE {
+            let chunk = self.chunks[region.chunk].take().unwrap();
+            if self.current == Some(region.chunk) {
+                self.current = None;
+            }
+            allocator.destroy_buffer(device, chunk.buffer, chunk.memory)?;
+        }
+        Ok(())
+    }
+
+    /// Return the memory held by the staging buffers.
+    pub fn memory_stats(&self, allocator: &A) -> ResourceStats {
+        let mut stats = ResourceStats::default();
+        for chunk in self.chunks.iter().flatten() {
+            stats.add_allocation(allocator.memory_size(&chunk.memory));
+        }
+        stats
+    }
+
+    /// Destroy all staging buffers. Regions must not be in use anymore.
+    pub fn destroy(&mut self, device: &Device, allocator: &mut A) -> RendererResult<()> {
+        self.current = None;
+        for chunk in self.chunks.drain(..).flatten() {
+            allocator.destroy_buffer(device, chunk.buffer, chunk.memory)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::allocator::mock::{device, MockAllocator};
+
+    fn chunk_data(pool: &StagingPool<MockAllocator>, region: StagingRegion) -> &[u8] {
+        let chunk = pool.chunks[region.chunk].as_ref().unwrap();
+        &chunk.memory.data[region.offset as usize..]
+    }
+
+    #[test]
+    fn regions_are_aligned_in_the_same_chunk() {
+        let device = device();
+        let mut allocator = MockAllocator::default();
+        let mut pool = StagingPool::new();
+
+        let first = pool.allocate(&device, &mut allocator, &[1; 10]).unwrap();
+        let second = pool.allocate(&device, &mut allocator, &[2; 20]).unwrap();
+        assert_eq!((first.offset, second.offset), (0, 16));
+        assert_eq!(first.buffer, second.buffer);
+        assert_eq!(allocator.created_buffers, 1);
+        assert_eq!(chunk_data(&pool, first)[..10], [1; 10]);
+        assert_eq!(chunk_data(&pool, second)[..20], [2; 20]);
+
+        pool.destroy(&device, &mut allocator).unwrap();
+        assert_eq!(allocator.live_buffers, 0);
+    }
+
+    #[test]
+    fn space_is_recycled_once_regions_are_freed() {
+        let device = device();
+        let mut allocator = MockAllocator::default();
+        let mut pool = StagingPool::new();
+
+        let first = pool.allocate(&device, &mut allocator, &[1; 64]).unwrap();
+        let second = pool.allocate(&device, &mut allocator, &[2; 64]).unwrap();
+        pool.free(&device, &mut allocator, first).unwrap();
+        let third = pool.allocate(&device, &mut allocator, &[3; 64]).unwrap();
+        assert_eq!(third.offset, 128);
+
+        pool.free(&device, &mut allocator, second).unwrap();
+        pool.free(&device, &mut allocator, third).unwrap();
+        let fourth = pool.allocate(&device, &mut allocator, &[4; 64]).unwrap();
+        assert_eq!(fourth.offset, 0);
+        assert_eq!(allocator.created_buffers, 1);
+
+        pool.free(&device, &mut allocator, fourth).unwrap();
+        pool.destroy(&device, &mut allocator).unwrap();
+    }
+
+    #[test]
+    fn full_chunks_are_reused_once_free() {
+        let device = device();
+        let mut allocator = MockAllocator::default();
+        let mut pool = StagingPool::new();
+        let data = vec![0; CHUNK_SIZE / 2 + 1];
+
+        let first = pool.allocate(&device, &mut allocator, &data).unwrap();
+        let second = pool.allocate(&device, &mut allocator, &data).unwrap();
+        assert_ne!(first.buffer, second.buffer);
+        assert_eq!(allocator.created_buffers, 2);
+
+        pool.free(&device, &mut allocator, first).unwrap();
+        let third = pool.allocate(&device, &mut allocator, &data).unwrap();
+        assert_eq!((third.buffer, third.offset), (first.buffer, 0));
+        assert_eq!(allocator.created_buffers, 2);
+
+        // Both chunks are in use so a new one is created.
+        let fourth = pool.allocate(&device, &mut allocator, &data).unwrap();
+        assert_eq!(allocator.created_buffers, 3);
+        assert_eq!(pool.memory_stats(&allocator).allocation_count, 3);
+
+        for region in [second, third, fourth] {
+            pool.free(&device, &mut allocator, region).unwrap();
+        }
+        pool.destroy(&device, &mut allocator).unwrap();
+        assert_eq!(allocator.live_buffers, 0);
+    }
+
+    #[test]
+    fn oversized_chunks_are_destroyed_once_free() {
+        let device = device();
+        let mut allocator = MockAllocator::default();
+        let mut pool = StagingPool::new();
+
+        let small = pool.allocate(&device, &mut allocator, &[1; 16]).unwrap();
+        let large = pool
+            .allocate(&device, &mut allocator, &vec![2; CHUNK_SIZE + 1])
+            .unwrap();
+        assert_ne!(small.buffer, large.buffer);
+        assert_eq!(
+            pool.memory_stats(&allocator).size,
+            (2 * CHUNK_SIZE + 1) as vk::DeviceSize
+        );
+
+        pool.free(&device, &mut allocator, large).unwrap();
+        assert_eq!(allocator.live_buffers, 1);
+        assert_eq!(
+            pool.memory_stats(&allocator).size,
+            CHUNK_SIZE as vk::DeviceSize
+        );
+
+        // The next upload goes back to the remaining chunk.
+        let next = pool.allocate(&device, &mut allocator, &[3; 16]).unwrap();
+        assert_eq!((next.buffer, next.offset), (small.buffer, 16));
+        assert_eq!(allocator.created_buffers, 2);
+
+        pool.free(&device, &mut allocator, small).unwrap();
+        pool.free(&device, &mut allocator, next).unwrap();
+        pool.destroy(&device, &mut allocator).unwrap();
+        assert_eq!(allocator.live_buffers, 0);
+    }
+}
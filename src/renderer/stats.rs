@@ -0,0 +1,88 @@
+use ash::vk;
+
+/// Memory held by a set of resources.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceStats {
+    /// Number of memory allocations.
+    pub allocation_count: usize,
+    /// Size in bytes of the memory allocations.
+    pub size: vk::DeviceSize,
+}
+
+impl ResourceStats {
+    pub(crate) fn add_allocation(&mut self, size: vk::DeviceSize) {
+        self.allocation_count += 1;
+        self.size += size;
+    }
+
+    pub(crate) fn merge(&mut self, other: ResourceStats) {
+        self.allocation_count += other.allocation_count;
+        self.size += other.size;
+    }
+}
+
+/// Memory held by the vertex/index buffer of one frame in flight.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeshStats {
+    /// Memory of the buffer, and of its staging buffer if any.
+    pub memory: ResourceStats,
+    /// Number of vertices the buffer can hold.
+    pub vertex_capacity: usize,
+    /// Number of vertices written during the last update.
+    pub vertex_count: usize,
+    /// Number of indices the buffer can hold.
+    pub index_capacity: usize,
+    /// Number of indices written during the last update.
+    pub index_count: usize,
+}
+
+/// Memory held by the renderer.
+///
+/// See [`Renderer::memory_stats`](crate::Renderer::memory_stats).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Memory of the fonts texture.
+    pub fonts_texture: ResourceStats,
+    /// Memory of the vertex/index buffers, one entry per frame in flight.
+    ///
+    /// Empty until the first draw.
+    pub frames: Vec<MeshStats>,
+    /// Memory of the textures created with [`Renderer::create_texture`](crate::Renderer::create_texture).
+    ///
+    /// Textures provided by the application through [`Renderer::textures`](crate::Renderer::textures)
+    /// are not accounted for.
+    pub textures: ResourceStats,
+    /// Memory of the resources waiting for frames in flight to complete before being destroyed.
+    ///
+    /// See [`Renderer::release_retired_resources`](crate::Renderer::release_retired_resources).
+    pub retired: ResourceStats,
+    /// Memory of the staging buffers the data of texture uploads is copied to.
+    pub staging: ResourceStats,
+}
+
+impl MemoryStats {
+    /// Return the memory held by all resources of the renderer.
+    pub fn total(&self) -> ResourceStats {
+        let mut total = self.fonts_texture;
+        self.frames.iter().for_each(|f| total.merge(f.memory));
+        total.merge(self.textures);
+        total.merge(self.retired);
+        total.merge(self.staging);
+        total
+    }
+}
+
+/// Usage of the descriptor pools of the renderer.
+///
+/// See [`Renderer::descriptor_pool_stats`](crate::Renderer::descriptor_pool_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DescriptorPoolStats {
+    /// Number of descriptor pools.
+    pub pool_count: usize,
+    /// Number of descriptor sets all pools can hold.
+    pub capacity: u32,
+    /// Number of descriptor sets used by the fonts texture, managed textures and registered images.
+    pub used_sets: u32,
+    /// Number of descriptor sets freed and kept to be reused.
+    pub recycled_sets: u32,
+}
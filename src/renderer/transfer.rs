@@ -0,0 +1,37 @@
+use ash::vk;
+
+/// Queue used to upload textures separately from the queue drawing the UI.
+///
+/// The command buffers of uploads are freed into `command_pool` by the renderer once the uploads
+/// complete, as frames are drawn, by [`Renderer::release_retired_resources`] or when the renderer
+/// is dropped. The pool must therefore outlive the renderer, or at least the last call to
+/// `release_retired_resources` made after all uploads completed.
+///
+/// [`Renderer::release_retired_resources`]: crate::Renderer::release_retired_resources
+///
+/// See [`Renderer::create_texture_with_transfer_queue`](crate::Renderer::create_texture_with_transfer_queue).
+#[derive(Debug, Clone, Copy)]
+pub struct TransferQueue {
+    /// Queue the uploads are submitted to.
+    pub queue: vk::Queue,
+    /// Family of `queue`.
+    pub queue_family_index: u32,
+    /// Pool the upload command buffers are allocated from. It must belong to the family of
+    /// `queue` and be externally synchronized with the renderer.
+    pub command_pool: vk::CommandPool,
+    /// Family of the queue the renderer's command buffers are submitted to.
+    pub graphics_queue_family_index: u32,
+}
+
+/// Semaphore signaled when an upload to a [`TransferQueue`] completes.
+///
+/// The submission drawing with the uploaded texture must wait on it.
+#[derive(Debug, Clone, Copy)]
+pub enum UploadSignal {
+    /// A binary semaphore.
+    Semaphore(vk::Semaphore),
+    /// A timeline semaphore and the value it is signaled with.
+    ///
+    /// The `timelineSemaphore` feature must be enabled when creating the device.
+    Timeline(vk::Semaphore, u64),
+}
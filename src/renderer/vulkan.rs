@@ -3,9 +3,8 @@
 //! A set of functions used to ease Vulkan resources creations. These are supposed to be internal but
 //! are exposed since they might help users create descriptors sets when using the custom textures.
 
-use crate::{Options, RendererResult};
-use ash::{vk, Device};
-pub(crate) use buffer::*;
+use crate::{Options, RendererError, RendererResult};
+use ash::{vk, Device, Instance};
 use std::{ffi::CString, mem};
 pub(crate) use texture::*;
 
@@ -21,6 +20,7 @@ pub(crate) unsafe fn any_as_u8_slice<T: Sized>(any: &T) -> &[u8] {
 /// Create a descriptor set layout compatible with the graphics pipeline.
 pub fn create_vulkan_descriptor_set_layout(
     device: &Device,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
 ) -> RendererResult<vk::DescriptorSetLayout> {
     log::debug!("Creating vulkan descriptor set layout");
     let bindings = [vk::DescriptorSetLayoutBinding::default()
@@ -33,12 +33,15 @@ pub fn create_vulkan_descriptor_set_layout(
     let descriptor_set_create_info =
         vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
 
-    unsafe { Ok(device.create_descriptor_set_layout(&descriptor_set_create_info, None)?) }
+    unsafe {
+        Ok(device.create_descriptor_set_layout(&descriptor_set_create_info, allocation_callbacks)?)
+    }
 }
 
 pub(crate) fn create_vulkan_pipeline_layout(
     device: &Device,
     descriptor_set_layout: vk::DescriptorSetLayout,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
 ) -> RendererResult<vk::PipelineLayout> {
     use ultraviolet::mat::Mat4;
 
@@ -53,7 +56,8 @@ pub(crate) fn create_vulkan_pipeline_layout(
     let layout_info = vk::PipelineLayoutCreateInfo::default()
         .set_layouts(&descriptor_set_layouts)
         .push_constant_ranges(&push_const_range);
-    let pipeline_layout = unsafe { device.create_pipeline_layout(&layout_info, None)? };
+    let pipeline_layout =
+        unsafe { device.create_pipeline_layout(&layout_info, allocation_callbacks)? };
     Ok(pipeline_layout)
 }
 
@@ -64,6 +68,7 @@ pub(crate) fn create_vulkan_pipeline(
     #[cfg(feature = "dynamic-rendering")] dynamic_rendering: DynamicRendering,
     options: Options,
 ) -> RendererResult<vk::Pipeline> {
+    let allocation_callbacks = options.allocation_callbacks.as_ref();
     let entry_point_name = CString::new("main").unwrap();
 
     let vertex_shader_source = std::include_bytes!("../shaders/shader.vert.spv");
@@ -71,11 +76,13 @@ pub(crate) fn create_vulkan_pipeline(
 
     let vertex_source = read_shader_from_source(vertex_shader_source)?;
     let vertex_create_info = vk::ShaderModuleCreateInfo::default().code(&vertex_source);
-    let vertex_module = unsafe { device.create_shader_module(&vertex_create_info, None)? };
+    let vertex_module =
+        unsafe { device.create_shader_module(&vertex_create_info, allocation_callbacks)? };
 
     let fragment_source = read_shader_from_source(fragment_shader_source)?;
     let fragment_create_info = vk::ShaderModuleCreateInfo::default().code(&fragment_source);
-    let fragment_module = unsafe { device.create_shader_module(&fragment_create_info, None)? };
+    let fragment_module =
+        unsafe { device.create_shader_module(&fragment_create_info, allocation_callbacks)? };
 
     let shader_states_infos = [
         vk::PipelineShaderStageCreateInfo::default()
@@ -216,14 +223,14 @@ pub(crate) fn create_vulkan_pipeline(
             .create_graphics_pipelines(
                 vk::PipelineCache::null(),
                 std::slice::from_ref(&pipeline_info),
-                None,
+                allocation_callbacks,
             )
             .map_err(|e| e.1)?[0]
     };
 
     unsafe {
-        device.destroy_shader_module(vertex_module, None);
-        device.destroy_shader_module(fragment_module, None);
+        device.destroy_shader_module(vertex_module, allocation_callbacks);
+        device.destroy_shader_module(fragment_module, allocation_callbacks);
     }
 
     Ok(pipeline)
@@ -239,18 +246,19 @@ fn read_shader_from_source(source: &[u8]) -> RendererResult<Vec<u32>> {
 pub fn create_vulkan_descriptor_pool(
     device: &Device,
     max_sets: u32,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
 ) -> RendererResult<vk::DescriptorPool> {
     log::debug!("Creating vulkan descriptor pool");
 
     let sizes = [vk::DescriptorPoolSize {
         ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-        descriptor_count: 1,
+        descriptor_count: max_sets,
     }];
     let create_info = vk::DescriptorPoolCreateInfo::default()
         .pool_sizes(&sizes)
         .max_sets(max_sets)
         .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET);
-    unsafe { Ok(device.create_descriptor_pool(&create_info, None)?) }
+    unsafe { Ok(device.create_descriptor_pool(&create_info, allocation_callbacks)?) }
 }
 
 /// Create a descriptor set compatible with the graphics pipeline from a texture.
@@ -272,11 +280,30 @@ pub fn create_vulkan_descriptor_set(
         unsafe { device.allocate_descriptor_sets(&allocate_info)?[0] }
     };
 
+    update_vulkan_descriptor_set(
+        device,
+        set,
+        image_view,
+        sampler,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    );
+
+    Ok(set)
+}
+
+/// Write the image and sampler sampled through a descriptor set compatible with the graphics pipeline.
+pub(crate) fn update_vulkan_descriptor_set(
+    device: &Device,
+    set: vk::DescriptorSet,
+    image_view: vk::ImageView,
+    sampler: vk::Sampler,
+    image_layout: vk::ImageLayout,
+) {
     unsafe {
         let image_info = [vk::DescriptorImageInfo {
             sampler,
             image_view,
-            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            image_layout,
         }];
 
         let writes = [vk::WriteDescriptorSet::default()
@@ -287,52 +314,428 @@ pub fn create_vulkan_descriptor_set(
             ];
         device.update_descriptor_sets(&writes, &[])
     }
-
-    Ok(set)
 }
 
-mod buffer {
-
-    use crate::{
-        renderer::allocator::{Allocate, Allocator, Memory},
-        RendererResult,
-    };
-    use ash::vk;
-    use ash::Device;
+/// Check that images of `format` with optimal tiling can be sampled.
+///
+/// Block compressed formats also require their device feature to be supported. Formats
+/// supporting sampling also support being the destination of transfers.
+pub(crate) fn check_texture_format_support(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> RendererResult<()> {
+    if let Some(feature) = CompressionFeature::of(format) {
+        let features = unsafe { instance.get_physical_device_features(physical_device) };
+        if !feature.is_supported(&features) {
+            return Err(RendererError::MissingDeviceFeature(format, feature.name()));
+        }
+    }
 
-    pub fn create_and_fill_buffer<T>(
-        device: &Device,
-        allocator: &mut Allocator,
-        data: &[T],
-        usage: vk::BufferUsageFlags,
-    ) -> RendererResult<(vk::Buffer, Memory)>
-    where
-        T: Copy,
+    let properties =
+        unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+    if properties
+        .optimal_tiling_features
+        .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
     {
-        let size = std::mem::size_of_val(data);
-        let (buffer, mut memory) = allocator.create_buffer(device, size, usage)?;
-        allocator.update_buffer(device, &mut memory, data)?;
-        Ok((buffer, memory))
+        Ok(())
+    } else {
+        Err(RendererError::UnsupportedFormat(format))
     }
 }
 
+/// Return true if mip levels of images of `format` can be generated with linear filtered blits.
+pub(crate) fn supports_linear_blit(
+    instance: &Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+) -> bool {
+    let properties =
+        unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+    properties.optimal_tiling_features.contains(
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+    )
+}
+
 mod texture {
 
-    use super::buffer::*;
-    use crate::renderer::allocator::{Allocate, Allocator, Memory};
-    use crate::RendererResult;
+    use crate::renderer::{
+        allocator::Allocate,
+        staging::{StagingPool, StagingRegion},
+    };
+    use crate::{RendererError, RendererResult, ResourceStats, UploadSignal};
     use ash::vk;
     use ash::Device;
 
+    /// Return the size in bytes of a texel of an uncompressed color `format`.
+    ///
+    /// Return `None` if the format is not supported for textures.
+    fn texel_size(format: vk::Format) -> Option<usize> {
+        let size = match format {
+            vk::Format::R8_UNORM | vk::Format::R8_SRGB => 1,
+            vk::Format::R8G8_UNORM
+            | vk::Format::R8G8_SRGB
+            | vk::Format::R16_UNORM
+            | vk::Format::R16_SFLOAT => 2,
+            vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::A2B10G10R10_UNORM_PACK32
+            | vk::Format::R16G16_UNORM
+            | vk::Format::R16G16_SFLOAT
+            | vk::Format::R32_SFLOAT => 4,
+            vk::Format::R16G16B16A16_UNORM
+            | vk::Format::R16G16B16A16_SFLOAT
+            | vk::Format::R32G32_SFLOAT => 8,
+            vk::Format::R32G32B32A32_SFLOAT => 16,
+            _ => return None,
+        };
+        Some(size)
+    }
+
+    /// Device feature required to sample a block compressed format.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CompressionFeature {
+        Bc,
+        Etc2,
+        AstcLdr,
+    }
+
+    impl CompressionFeature {
+        /// Return the feature required by `format`, or `None` if it is not block compressed.
+        pub fn of(format: vk::Format) -> Option<Self> {
+            compressed_block(format).map(|(_, feature)| feature)
+        }
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Self::Bc => "textureCompressionBC",
+                Self::Etc2 => "textureCompressionETC2",
+                Self::AstcLdr => "textureCompressionASTC_LDR",
+            }
+        }
+
+        pub fn is_supported(self, features: &vk::PhysicalDeviceFeatures) -> bool {
+            let supported = match self {
+                Self::Bc => features.texture_compression_bc,
+                Self::Etc2 => features.texture_compression_etc2,
+                Self::AstcLdr => features.texture_compression_astc_ldr,
+            };
+            supported == vk::TRUE
+        }
+    }
+
+    /// Return the block layout of a block compressed `format` and the feature it requires.
+    fn compressed_block(format: vk::Format) -> Option<(FormatBlock, CompressionFeature)> {
+        use vk::Format as F;
+        use CompressionFeature::*;
+
+        let (width, height, size, feature) = match format {
+            F::BC1_RGB_UNORM_BLOCK
+            | F::BC1_RGB_SRGB_BLOCK
+            | F::BC1_RGBA_UNORM_BLOCK
+            | F::BC1_RGBA_SRGB_BLOCK
+            | F::BC4_UNORM_BLOCK
+            | F::BC4_SNORM_BLOCK => (4, 4, 8, Bc),
+            F::BC2_UNORM_BLOCK
+            | F::BC2_SRGB_BLOCK
+            | F::BC3_UNORM_BLOCK
+            | F::BC3_SRGB_BLOCK
+            | F::BC5_UNORM_BLOCK
+            | F::BC5_SNORM_BLOCK
+            | F::BC6H_UFLOAT_BLOCK
+            | F::BC6H_SFLOAT_BLOCK
+            | F::BC7_UNORM_BLOCK
+            | F::BC7_SRGB_BLOCK => (4, 4, 16, Bc),
+            F::ETC2_R8G8B8_UNORM_BLOCK
+            | F::ETC2_R8G8B8_SRGB_BLOCK
+            | F::ETC2_R8G8B8A1_UNORM_BLOCK
+            | F::ETC2_R8G8B8A1_SRGB_BLOCK
+            | F::EAC_R11_UNORM_BLOCK
+            | F::EAC_R11_SNORM_BLOCK => (4, 4, 8, Etc2),
+            F::ETC2_R8G8B8A8_UNORM_BLOCK
+            | F::ETC2_R8G8B8A8_SRGB_BLOCK
+            | F::EAC_R11G11_UNORM_BLOCK
+            | F::EAC_R11G11_SNORM_BLOCK => (4, 4, 16, Etc2),
+            F::ASTC_4X4_UNORM_BLOCK | F::ASTC_4X4_SRGB_BLOCK => (4, 4, 16, AstcLdr),
+            F::ASTC_5X4_UNORM_BLOCK | F::ASTC_5X4_SRGB_BLOCK => (5, 4, 16, AstcLdr),
+            F::ASTC_5X5_UNORM_BLOCK | F::ASTC_5X5_SRGB_BLOCK => (5, 5, 16, AstcLdr),
+            F::ASTC_6X5_UNORM_BLOCK | F::ASTC_6X5_SRGB_BLOCK => (6, 5, 16, AstcLdr),
+            F::ASTC_6X6_UNORM_BLOCK | F::ASTC_6X6_SRGB_BLOCK => (6, 6, 16, AstcLdr),
+            F::ASTC_8X5_UNORM_BLOCK | F::ASTC_8X5_SRGB_BLOCK => (8, 5, 16, AstcLdr),
+            F::ASTC_8X6_UNORM_BLOCK | F::ASTC_8X6_SRGB_BLOCK => (8, 6, 16, AstcLdr),
+            F::ASTC_8X8_UNORM_BLOCK | F::ASTC_8X8_SRGB_BLOCK => (8, 8, 16, AstcLdr),
+            F::ASTC_10X5_UNORM_BLOCK | F::ASTC_10X5_SRGB_BLOCK => (10, 5, 16, AstcLdr),
+            F::ASTC_10X6_UNORM_BLOCK | F::ASTC_10X6_SRGB_BLOCK => (10, 6, 16, AstcLdr),
+            F::ASTC_10X8_UNORM_BLOCK | F::ASTC_10X8_SRGB_BLOCK => (10, 8, 16, AstcLdr),
+            F::ASTC_10X10_UNORM_BLOCK | F::ASTC_10X10_SRGB_BLOCK => (10, 10, 16, AstcLdr),
+            F::ASTC_12X10_UNORM_BLOCK | F::ASTC_12X10_SRGB_BLOCK => (12, 10, 16, AstcLdr),
+            F::ASTC_12X12_UNORM_BLOCK | F::ASTC_12X12_SRGB_BLOCK => (12, 12, 16, AstcLdr),
+            _ => return None,
+        };
+
+        Some((
+            FormatBlock {
+                width,
+                height,
+                size,
+            },
+            feature,
+        ))
+    }
+
+    /// Dimensions in texels and size in bytes of the blocks texels of a format are stored in.
+    ///
+    /// Blocks of uncompressed formats are single texels.
+    #[derive(Debug, Clone, Copy)]
+    pub struct FormatBlock {
+        pub width: u32,
+        pub height: u32,
+        pub size: usize,
+    }
+
+    impl FormatBlock {
+        /// Return the block layout of `format`.
+        pub fn of(format: vk::Format) -> RendererResult<Self> {
+            texel_size(format)
+                .map(|size| Self {
+                    width: 1,
+                    height: 1,
+                    size,
+                })
+                .or_else(|| compressed_block(format).map(|(block, _)| block))
+                .ok_or(RendererError::UnsupportedFormat(format))
+        }
+
+        /// Return the size in bytes of a region of `width` x `height` texels,
+        /// or `None` if it overflows.
+        pub fn region_size(&self, width: u32, height: u32) -> Option<usize> {
+            (width.div_ceil(self.width) as usize)
+                .checked_mul(height.div_ceil(self.height) as usize)?
+                .checked_mul(self.size)
+        }
+    }
+
+    /// Return the extent of a mip level of an image of `width` x `height` texels.
+    pub fn mip_level_extent(width: u32, height: u32, level: u32) -> (u32, u32) {
+        ((width >> level).max(1), (height >> level).max(1))
+    }
+
+    /// Return the number of levels of a full mip chain of a `width` x `height` image.
+    pub fn full_mip_levels(width: u32, height: u32) -> u32 {
+        u32::BITS - width.max(height).max(1).leading_zeros()
+    }
+
+    /// Check that `data` holds exactly the `mip_levels` first mip levels of a non empty
+    /// `width` x `height` image of `format`, and return the offset of each level in `data`.
+    fn check_data_size(
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        data: &[u8],
+    ) -> RendererResult<Vec<usize>> {
+        let block = FormatBlock::of(format)?;
+
+        if width == 0 || height == 0 {
+            return Err(RendererError::BadTextureData(format!(
+                "A {width}x{height} image is empty"
+            )));
+        }
+
+        let max_mip_levels = full_mip_levels(width, height);
+        if mip_levels == 0 || mip_levels > max_mip_levels {
+            return Err(RendererError::BadTextureData(format!(
+                "A {width}x{height} image has between 1 and {max_mip_levels} mip levels but got {mip_levels}"
+            )));
+        }
+
+        let mut offsets = Vec::with_capacity(mip_levels as usize);
+        let mut expected_len = 0usize;
+        for level in 0..mip_levels {
+            offsets.push(expected_len);
+            let (level_width, level_height) = mip_level_extent(width, height, level);
+            expected_len = block
+                .region_size(level_width, level_height)
+                .and_then(|size| expected_len.checked_add(size))
+                .ok_or_else(|| {
+                    RendererError::BadTextureData(format!(
+                        "A {width}x{height} image of {format:?} is too large"
+                    ))
+                })?;
+        }
+
+        if data.len() != expected_len {
+            return Err(RendererError::BadTextureData(format!(
+                "Expected {expected_len} bytes of {format:?} data but got {}",
+                data.len()
+            )));
+        }
+        Ok(offsets)
+    }
+
+    /// Alignment of the mip levels in staging buffers.
+    ///
+    /// Copies recorded for queues without graphics or compute capabilities require buffer offsets
+    /// aligned to 4 bytes. Texel and block sizes are powers of two so levels still start on a
+    /// texel or block.
+    const LEVEL_ALIGNMENT: usize = 4;
+
+    /// Zeros written between mip levels to align them in staging buffers.
+    static LEVEL_PADDING: [u8; LEVEL_ALIGNMENT] = [0; LEVEL_ALIGNMENT];
+
+    /// Return the slices to copy to a staging buffer so that each mip level of `data` starts
+    /// at an offset aligned to [`LEVEL_ALIGNMENT`], and the offset of each level in the copy.
+    fn staging_levels<'a>(
+        data: &'a [u8],
+        level_offsets: &[usize],
+    ) -> (Vec<&'a [u8]>, Vec<usize>) {
+        let mut slices = Vec::with_capacity(level_offsets.len() * 2);
+        let mut staging_offsets = Vec::with_capacity(level_offsets.len());
+        let mut staging_len = 0usize;
+        for (level, &start) in level_offsets.iter().enumerate() {
+            let end = level_offsets.get(level + 1).copied().unwrap_or(data.len());
+            let staging_offset = staging_len.next_multiple_of(LEVEL_ALIGNMENT);
+            slices.push(&LEVEL_PADDING[..staging_offset - staging_len]);
+            slices.push(&data[start..end]);
+            staging_offsets.push(staging_offset);
+            staging_len = staging_offset + end - start;
+        }
+        (slices, staging_offsets)
+    }
+
+    /// Return the copies of the mip levels of a `width` x `height` image from a staging buffer,
+    /// the levels starting at `staging_offsets` after `buffer_offset`.
+    fn level_copies(
+        width: u32,
+        height: u32,
+        buffer_offset: vk::DeviceSize,
+        staging_offsets: &[usize],
+    ) -> Vec<vk::BufferImageCopy> {
+        // Extents of compressed levels may not be multiples of the block size since each level
+        // is copied up to its edges.
+        staging_offsets
+            .iter()
+            .zip(0..)
+            .map(|(&offset, level)| {
+                let (level_width, level_height) = mip_level_extent(width, height, level);
+                vk::BufferImageCopy::default()
+                    .buffer_offset(buffer_offset + offset as vk::DeviceSize)
+                    .buffer_row_length(0)
+                    .buffer_image_height(0)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D {
+                        width: level_width,
+                        height: level_height,
+                        depth: 1,
+                    })
+            })
+            .collect()
+    }
+
+    /// Transfer of the ownership of an image between two queue families.
+    #[derive(Debug, Clone, Copy)]
+    pub struct QueueFamilyTransfer {
+        /// Family of the queue the image is uploaded with.
+        pub src_queue_family_index: u32,
+        /// Family of the queue the image is drawn with.
+        pub dst_queue_family_index: u32,
+    }
+
+    /// Check the data of a texture upload and return the offset of each level in `data`.
+    fn check_upload(
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        generate_mipmaps: bool,
+        data: &[u8],
+    ) -> RendererResult<Vec<usize>> {
+        if generate_mipmaps && mip_levels != 1 {
+            return Err(RendererError::BadTextureData(format!(
+                "Mip levels are generated from a single level but got {mip_levels}"
+            )));
+        }
+        check_data_size(format, width, height, mip_levels, data)
+    }
+
+    /// Check that `data` can update a non empty region of `size` texels at `offset` of a
+    /// `texture_width` x `texture_height` texture of `format`.
+    ///
+    /// The texture size must have been checked with [`check_data_size`].
+    fn check_region(
+        format: vk::Format,
+        texture_width: u32,
+        texture_height: u32,
+        offset: [u32; 2],
+        size: [u32; 2],
+        data: &[u8],
+    ) -> RendererResult<()> {
+        let [x, y] = offset;
+        let [width, height] = size;
+        if width == 0 || height == 0 {
+            return Err(RendererError::BadTextureData(format!(
+                "Region of {width}x{height} at ({x}, {y}) is empty"
+            )));
+        }
+        if x as u64 + width as u64 > texture_width as u64
+            || y as u64 + height as u64 > texture_height as u64
+        {
+            return Err(RendererError::BadTextureData(format!(
+                "Region of {width}x{height} at ({x}, {y}) exceeds the texture size of {texture_width}x{texture_height}"
+            )));
+        }
+
+        let block = FormatBlock::of(format)?;
+        let aligned = |offset: u32, size: u32, block_size: u32, max: u32| {
+            offset.is_multiple_of(block_size)
+                && (size.is_multiple_of(block_size) || offset + size == max)
+        };
+        if !aligned(x, width, block.width, texture_width)
+            || !aligned(y, height, block.height, texture_height)
+        {
+            return Err(RendererError::BadTextureData(format!(
+                "Region of {width}x{height} at ({x}, {y}) is not aligned to the {}x{} blocks of {:?}",
+                block.width, block.height, format
+            )));
+        }
+
+        // The region fits in the texture whose size was checked.
+        let expected_len = block.region_size(width, height).unwrap();
+        if data.len() != expected_len {
+            return Err(RendererError::BadTextureData(format!(
+                "Expected {expected_len} bytes of {format:?} data but got {}",
+                data.len()
+            )));
+        }
+        Ok(())
+    }
+
     /// Helper struct representing a sampled texture.
-    pub struct Texture {
+    ///
+    /// The sampler is shared with other textures and is not destroyed with the texture.
+    pub struct Texture<A: Allocate> {
         pub image: vk::Image,
-        image_mem: Memory,
+        image_mem: A::Memory,
         pub image_view: vk::ImageView,
         pub sampler: vk::Sampler,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+        generated_mipmaps: bool,
     }
 
-    impl Texture {
+    impl<A: Allocate> Texture<A> {
         /// Create a texture from an `u8` array containing an rgba image.
         ///
         /// The image data is device local and it's format is R8G8B8A8_UNORM.
@@ -343,44 +746,261 @@ mod texture {
         /// * `queue` - The queue with transfer capabilities to execute commands.
         /// * `command_pool` - The command pool used to create a command buffer used to record commands.
         /// * `allocator` - Allocator used to allocate memory for the image.
+        /// * `staging` - Staging buffers the image data is copied to.
+        /// * `allocation_callbacks` - Host memory allocation callbacks used to create the image view.
+        /// * `sampler` - The sampler of the texture. It is not owned by the texture.
         /// * `width` - The width of the image.
         /// * `height` - The height of the image.
         /// * `data` - The image data.
+        #[allow(clippy::too_many_arguments)]
+        #[track_caller]
         pub fn from_rgba8(
             device: &Device,
             queue: vk::Queue,
             command_pool: vk::CommandPool,
-            allocator: &mut Allocator,
+            allocator: &mut A,
+            staging: &mut StagingPool<A>,
+            allocation_callbacks: Option<&vk::AllocationCallbacks>,
+            sampler: vk::Sampler,
             width: u32,
             height: u32,
             data: &[u8],
         ) -> RendererResult<Self> {
-            let (texture, staging_buff, staging_mem) =
-                execute_one_time_commands(device, queue, command_pool, |buffer| {
-                    Self::cmd_from_rgba(device, allocator, buffer, width, height, data)
-                })??;
+            Self::from_data(
+                device,
+                queue,
+                command_pool,
+                allocator,
+                staging,
+                allocation_callbacks,
+                sampler,
+                vk::Format::R8G8B8A8_UNORM,
+                width,
+                height,
+                1,
+                false,
+                data,
+            )
+        }
 
-            allocator.destroy_buffer(device, staging_buff, staging_mem)?;
+        /// Create a texture from an `u8` array containing an image and its mip levels.
+        ///
+        /// The image data is device local. `format` is an uncompressed color format or a BC, ETC2
+        /// or ASTC LDR block compressed format. The caller is responsible for checking that the
+        /// device supports sampling images of `format`.
+        ///
+        /// # Arguments
+        ///
+        /// * `device` - The Vulkan logical device.
+        /// * `queue` - The queue with transfer capabilities to execute commands.
+        /// * `command_pool` - The command pool used to create a command buffer used to record commands.
+        /// * `allocator` - Allocator used to allocate memory for the image.
+        /// * `staging` - Staging buffers the image data is copied to.
+        /// * `allocation_callbacks` - Host memory allocation callbacks used to create the image view.
+        /// * `sampler` - The sampler of the texture. It is not owned by the texture.
+        /// * `format` - The format of the image.
+        /// * `width` - The width of the image.
+        /// * `height` - The height of the image.
+        /// * `mip_levels` - The number of mip levels in `data`.
+        /// * `generate_mipmaps` - If true the full mip chain is generated from the single level of
+        ///   `data` with linear filtered blits. The caller is responsible for checking that `format`
+        ///   supports them.
+        /// * `data` - The image data. Mip levels are stored one after the other starting with
+        ///   the largest one, each as tightly packed texels or blocks of `format`.
+        #[allow(clippy::too_many_arguments)]
+        #[track_caller]
+        pub fn from_data(
+            device: &Device,
+            queue: vk::Queue,
+            command_pool: vk::CommandPool,
+            allocator: &mut A,
+            staging: &mut StagingPool<A>,
+            allocation_callbacks: Option<&vk::AllocationCallbacks>,
+            sampler: vk::Sampler,
+            format: vk::Format,
+            width: u32,
+            height: u32,
+            mip_levels: u32,
+            generate_mipmaps: bool,
+            data: &[u8],
+        ) -> RendererResult<Self> {
+            let level_offsets =
+                check_upload(format, width, height, mip_levels, generate_mipmaps, data)?;
+
+            let command_buffer = begin_one_time_commands(device, command_pool)?;
+            let recorded = Self::record_from_data(
+                device,
+                allocator,
+                staging,
+                allocation_callbacks,
+                sampler,
+                command_buffer,
+                format,
+                width,
+                height,
+                &level_offsets,
+                generate_mipmaps,
+                None,
+                data,
+            );
+            let (texture, region) = match recorded {
+                Ok(recorded) => recorded,
+                Err(error) => {
+                    abort_one_time_commands(device, command_pool, command_buffer);
+                    return Err(error);
+                }
+            };
+
+            let executed = execute_one_time_commands(device, queue, command_pool, command_buffer);
+            staging.free(device, allocator, region)?;
+            if let Err(error) = executed {
+                texture.destroy(device, allocator, allocation_callbacks)?;
+                return Err(error);
+            }
 
             Ok(texture)
         }
 
-        fn cmd_from_rgba(
+        /// Record the creation of a texture from an `u8` array containing an image and its mip levels.
+        ///
+        /// Same as [`Texture::from_data`] but the upload is recorded into `command_buffer` instead
+        /// of being submitted. The staging region is returned. It must not be freed until the
+        /// command buffer has completed execution, and the texture must be kept alive until then.
+        /// Commands must be recorded outside of a render pass.
+        ///
+        /// # Arguments
+        ///
+        /// * `device` - The Vulkan logical device.
+        /// * `allocator` - Allocator used to allocate memory for the image.
+        /// * `staging` - Staging buffers the image data is copied to.
+        /// * `allocation_callbacks` - Host memory allocation callbacks used to create the image view.
+        /// * `sampler` - The sampler of the texture. It is not owned by the texture.
+        /// * `command_buffer` - The command buffer commands will be recorded to.
+        /// * `format` - The format of the image.
+        /// * `width` - The width of the image.
+        /// * `height` - The height of the image.
+        /// * `mip_levels` - The number of mip levels in `data`.
+        /// * `generate_mipmaps` - If true the full mip chain is generated from the single level of `data`.
+        /// * `release` - If set, the image is released to another queue family after the copy
+        ///   instead of being made readable. Mip levels are then generated by [`Texture::cmd_acquire`].
+        /// * `data` - The image data.
+        #[allow(clippy::too_many_arguments)]
+        #[track_caller]
+        pub fn cmd_from_data(
             device: &Device,
-            allocator: &mut Allocator,
+            allocator: &mut A,
+            staging: &mut StagingPool<A>,
+            allocation_callbacks: Option<&vk::AllocationCallbacks>,
+            sampler: vk::Sampler,
             command_buffer: vk::CommandBuffer,
+            format: vk::Format,
             width: u32,
             height: u32,
+            mip_levels: u32,
+            generate_mipmaps: bool,
+            release: Option<QueueFamilyTransfer>,
             data: &[u8],
-        ) -> RendererResult<(Self, vk::Buffer, Memory)> {
-            let (buffer, buffer_mem) = create_and_fill_buffer(
+        ) -> RendererResult<(Self, StagingRegion)> {
+            let level_offsets =
+                check_upload(format, width, height, mip_levels, generate_mipmaps, data)?;
+
+            Self::record_from_data(
                 device,
                 allocator,
+                staging,
+                allocation_callbacks,
+                sampler,
+                command_buffer,
+                format,
+                width,
+                height,
+                &level_offsets,
+                generate_mipmaps,
+                release,
                 data,
-                vk::BufferUsageFlags::TRANSFER_SRC,
-            )?;
+            )
+        }
 
-            let (image, image_mem) = allocator.create_image(device, width, height)?;
+        #[allow(clippy::too_many_arguments)]
+        #[track_caller]
+        fn record_from_data(
+            device: &Device,
+            allocator: &mut A,
+            staging: &mut StagingPool<A>,
+            allocation_callbacks: Option<&vk::AllocationCallbacks>,
+            sampler: vk::Sampler,
+            command_buffer: vk::CommandBuffer,
+            format: vk::Format,
+            width: u32,
+            height: u32,
+            level_offsets: &[usize],
+            generate_mipmaps: bool,
+            release: Option<QueueFamilyTransfer>,
+            data: &[u8],
+        ) -> RendererResult<(Self, StagingRegion)> {
+            let (mip_levels, usage) = if generate_mipmaps {
+                (
+                    full_mip_levels(width, height),
+                    vk::ImageUsageFlags::TRANSFER_SRC
+                        | vk::ImageUsageFlags::TRANSFER_DST
+                        | vk::ImageUsageFlags::SAMPLED,
+                )
+            } else {
+                (
+                    level_offsets.len() as u32,
+                    vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                )
+            };
+
+            let (slices, staging_offsets) = staging_levels(data, level_offsets);
+            let region = staging.allocate_slices(device, allocator, &slices)?;
+
+            let image_info = vk::ImageCreateInfo::default()
+                .image_type(vk::ImageType::TYPE_2D)
+                .extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .mip_levels(mip_levels)
+                .array_layers(1)
+                .format(format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .samples(vk::SampleCountFlags::TYPE_1);
+            let (image, image_mem) = match allocator.create_image(device, &image_info) {
+                Ok(image) => image,
+                Err(error) => {
+                    staging.free(device, allocator, region)?;
+                    return Err(error);
+                }
+            };
+
+            // Create the view before recording commands so nothing is left to undo on failure.
+            let image_view = {
+                let create_info = vk::ImageViewCreateInfo::default()
+                    .image(image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(format)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: mip_levels,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    });
+
+                match unsafe { device.create_image_view(&create_info, allocation_callbacks) } {
+                    Ok(image_view) => image_view,
+                    Err(error) => {
+                        allocator.destroy_image(device, image, image_mem)?;
+                        staging.free(device, allocator, region)?;
+                        return Err(error.into());
+                    }
+                }
+            };
 
             // Transition the image layout and copy the buffer into the image
             // and transition the layout again to be readable from fragment shader.
@@ -394,7 +1014,7 @@ mod texture {
                     .subresource_range(vk::ImageSubresourceRange {
                         aspect_mask: vk::ImageAspectFlags::COLOR,
                         base_mip_level: 0,
-                        level_count: 1,
+                        level_count: mip_levels,
                         base_array_layer: 0,
                         layer_count: 1,
                     })
@@ -414,33 +1034,182 @@ mod texture {
                     )
                 };
 
-                let region = vk::BufferImageCopy::default()
-                    .buffer_offset(0)
-                    .buffer_row_length(0)
-                    .buffer_image_height(0)
-                    .image_subresource(vk::ImageSubresourceLayers {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        mip_level: 0,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    })
-                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
-                    .image_extent(vk::Extent3D {
-                        width,
-                        height,
-                        depth: 1,
-                    })
-                    ;
+                let regions = level_copies(width, height, region.offset, &staging_offsets);
                 unsafe {
                     device.cmd_copy_buffer_to_image(
                         command_buffer,
-                        buffer,
+                        region.buffer,
                         image,
                         vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                        &[region],
+                        &regions,
                     )
                 }
 
+                if let Some(transfer) = release {
+                    // Generated levels are written by the queue family acquiring the image.
+                    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+                    barrier.new_layout = acquired_layout(generate_mipmaps);
+                    barrier.src_queue_family_index = transfer.src_queue_family_index;
+                    barrier.dst_queue_family_index = transfer.dst_queue_family_index;
+                    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+                    barrier.dst_access_mask = vk::AccessFlags::empty();
+
+                    unsafe {
+                        device.cmd_pipeline_barrier(
+                            command_buffer,
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                            vk::DependencyFlags::empty(),
+                            &[],
+                            &[],
+                            &[barrier],
+                        )
+                    };
+                } else if generate_mipmaps {
+                    cmd_generate_mipmaps(device, command_buffer, image, width, height, mip_levels);
+                } else {
+                    barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
+                    barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+                    barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
+                    barrier.dst_access_mask = vk::AccessFlags::SHADER_READ;
+
+                    unsafe {
+                        device.cmd_pipeline_barrier(
+                            command_buffer,
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::PipelineStageFlags::FRAGMENT_SHADER,
+                            vk::DependencyFlags::empty(),
+                            &[],
+                            &[],
+                            &[barrier],
+                        )
+                    };
+                }
+            }
+
+            let texture = Self {
+                image,
+                image_mem,
+                image_view,
+                sampler,
+                format,
+                width,
+                height,
+                mip_levels,
+                generated_mipmaps: generate_mipmaps,
+            };
+
+            Ok((texture, region))
+        }
+
+        /// Record the update of a region of the texture from an `u8` array containing texels in the texture format.
+        ///
+        /// The data is copied to a staging region which is returned. It must not be freed until
+        /// the command buffer has completed execution. Commands must be recorded outside of a
+        /// render pass.
+        ///
+        /// Only the first mip level is updated. If the mip chain was generated when the texture
+        /// was created, it is generated again from the updated level.
+        ///
+        /// # Arguments
+        ///
+        /// * `device` - The Vulkan logical device.
+        /// * `allocator` - Allocator of the staging buffers.
+        /// * `staging` - Staging buffers the region data is copied to.
+        /// * `command_buffer` - The command buffer commands will be recorded to.
+        /// * `offset` - The offset in pixels of the region.
+        /// * `size` - The size in pixels of the region.
+        /// * `data` - The region data.
+        #[allow(clippy::too_many_arguments)]
+        #[track_caller]
+        pub fn cmd_update_region(
+            &self,
+            device: &Device,
+            allocator: &mut A,
+            staging: &mut StagingPool<A>,
+            command_buffer: vk::CommandBuffer,
+            offset: [u32; 2],
+            size: [u32; 2],
+            data: &[u8],
+        ) -> RendererResult<StagingRegion> {
+            check_region(self.format, self.width, self.height, offset, size, data)?;
+            let [x, y] = offset;
+            let [width, height] = size;
+
+            let staging_region = staging.allocate(device, allocator, data)?;
+
+            // Generated levels are all written again so they are transitioned with the first one.
+            let level_count = if self.generated_mipmaps {
+                self.mip_levels
+            } else {
+                1
+            };
+            let mut barrier = vk::ImageMemoryBarrier::default()
+                .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .src_access_mask(vk::AccessFlags::SHADER_READ)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE);
+
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                )
+            };
+
+            let region = vk::BufferImageCopy::default()
+                .buffer_offset(staging_region.offset)
+                .image_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .image_offset(vk::Offset3D {
+                    x: x as _,
+                    y: y as _,
+                    z: 0,
+                })
+                .image_extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                });
+            unsafe {
+                device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging_region.buffer,
+                    self.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                )
+            }
+
+            if self.generated_mipmaps {
+                cmd_generate_mipmaps(
+                    device,
+                    command_buffer,
+                    self.image,
+                    self.width,
+                    self.height,
+                    self.mip_levels,
+                );
+            } else {
                 barrier.old_layout = vk::ImageLayout::TRANSFER_DST_OPTIMAL;
                 barrier.new_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
                 barrier.src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
@@ -459,94 +1228,255 @@ mod texture {
                 };
             }
 
-            let image_view = {
-                let create_info = vk::ImageViewCreateInfo::default()
-                    .image(image)
-                    .view_type(vk::ImageViewType::TYPE_2D)
-                    .format(vk::Format::R8G8B8A8_UNORM)
-                    .subresource_range(vk::ImageSubresourceRange {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        base_mip_level: 0,
-                        level_count: 1,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    });
+            Ok(staging_region)
+        }
 
-                unsafe { device.create_image_view(&create_info, None)? }
+        /// Record the acquisition of the texture by the queue family it is drawn with.
+        ///
+        /// Must match the release recorded by [`Texture::cmd_from_data`], and be executed after
+        /// it. Mip levels are generated if requested at creation, which requires `command_buffer`
+        /// to be executed by a queue supporting graphics operations.
+        pub fn cmd_acquire(
+            &self,
+            device: &Device,
+            command_buffer: vk::CommandBuffer,
+            transfer: QueueFamilyTransfer,
+        ) {
+            let (dst_stage_mask, dst_access_mask) = if self.generated_mipmaps {
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+                )
+            } else {
+                (
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                )
             };
+            let barrier = vk::ImageMemoryBarrier::default()
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(acquired_layout(self.generated_mipmaps))
+                .src_queue_family_index(transfer.src_queue_family_index)
+                .dst_queue_family_index(transfer.dst_queue_family_index)
+                .image(self.image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: self.mip_levels,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_access_mask(dst_access_mask);
 
-            let sampler = {
-                let sampler_info = vk::SamplerCreateInfo::default()
-                    .mag_filter(vk::Filter::LINEAR)
-                    .min_filter(vk::Filter::LINEAR)
-                    .address_mode_u(vk::SamplerAddressMode::REPEAT)
-                    .address_mode_v(vk::SamplerAddressMode::REPEAT)
-                    .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                    .anisotropy_enable(false)
-                    .max_anisotropy(1.0)
-                    .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-                    .unnormalized_coordinates(false)
-                    .compare_enable(false)
-                    .compare_op(vk::CompareOp::ALWAYS)
-                    .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-                    .mip_lod_bias(0.0)
-                    .min_lod(0.0)
-                    .max_lod(1.0);
-                unsafe { device.create_sampler(&sampler_info, None)? }
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    dst_stage_mask,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                )
             };
 
-            let texture = Self {
-                image,
-                image_mem,
-                image_view,
-                sampler,
-            };
+            if self.generated_mipmaps {
+                cmd_generate_mipmaps(
+                    device,
+                    command_buffer,
+                    self.image,
+                    self.width,
+                    self.height,
+                    self.mip_levels,
+                );
+            }
+        }
 
-            Ok((texture, buffer, buffer_mem))
+        /// Return the memory held by the texture's image.
+        pub fn memory_stats(&self, allocator: &A) -> ResourceStats {
+            let mut stats = ResourceStats::default();
+            stats.add_allocation(allocator.memory_size(&self.image_mem));
+            stats
         }
 
         /// Free texture's resources.
-        pub fn destroy(self, device: &Device, allocator: &mut Allocator) -> RendererResult<()> {
+        pub fn destroy(
+            self,
+            device: &Device,
+            allocator: &mut A,
+            allocation_callbacks: Option<&vk::AllocationCallbacks>,
+        ) -> RendererResult<()> {
             unsafe {
-                device.destroy_sampler(self.sampler, None);
-                device.destroy_image_view(self.image_view, None);
+                device.destroy_image_view(self.image_view, allocation_callbacks);
                 allocator.destroy_image(device, self.image, self.image_mem)?;
             }
             Ok(())
         }
     }
 
-    fn execute_one_time_commands<R, F: FnOnce(vk::CommandBuffer) -> R>(
-        device: &Device,
-        queue: vk::Queue,
-        pool: vk::CommandPool,
-        executor: F,
-    ) -> RendererResult<R> {
-        let command_buffer = {
-            let alloc_info = vk::CommandBufferAllocateInfo::default()
-                .level(vk::CommandBufferLevel::PRIMARY)
-                .command_pool(pool)
-                .command_buffer_count(1);
+    /// Layout of an image transferred between queue families, which must be the same on both sides.
+    fn acquired_layout(generate_mipmaps: bool) -> vk::ImageLayout {
+        if generate_mipmaps {
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL
+        } else {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        }
+    }
 
-            unsafe { device.allocate_command_buffers(&alloc_info)?[0] }
+    /// Record the generation of the mip levels of an image from its first level.
+    ///
+    /// All levels must be in the `TRANSFER_DST_OPTIMAL` layout. Each level is blitted from the
+    /// previous one with linear filtering. All levels end up in the `SHADER_READ_ONLY_OPTIMAL` layout.
+    fn cmd_generate_mipmaps(
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        image: vk::Image,
+        width: u32,
+        height: u32,
+        mip_levels: u32,
+    ) {
+        let level_barrier = |level: u32,
+                             old_layout: vk::ImageLayout,
+                             new_layout: vk::ImageLayout,
+                             src_access_mask: vk::AccessFlags,
+                             dst_access_mask: vk::AccessFlags| {
+            vk::ImageMemoryBarrier::default()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: level,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
         };
-        let command_buffers = [command_buffer];
 
-        // Begin recording
-        {
-            let begin_info = vk::CommandBufferBeginInfo::default()
-                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-            unsafe { device.begin_command_buffer(command_buffer, &begin_info)? };
+        for level in 1..mip_levels {
+            // The previous level was written by the copy or the previous blit.
+            let barrier = level_barrier(
+                level - 1,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            );
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                )
+            };
+
+            let (src_width, src_height) = mip_level_extent(width, height, level - 1);
+            let (dst_width, dst_height) = mip_level_extent(width, height, level);
+            let blit = vk::ImageBlit::default()
+                .src_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: level - 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .src_offsets([
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: src_width as _,
+                        y: src_height as _,
+                        z: 1,
+                    },
+                ])
+                .dst_subresource(vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: level,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                })
+                .dst_offsets([
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: dst_width as _,
+                        y: dst_height as _,
+                        z: 1,
+                    },
+                ]);
+            unsafe {
+                device.cmd_blit_image(
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit],
+                    vk::Filter::LINEAR,
+                )
+            };
+
+            let barrier = level_barrier(
+                level - 1,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::AccessFlags::SHADER_READ,
+            );
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[barrier],
+                )
+            };
         }
 
-        // Execute user function
-        let executor_result = executor(command_buffer);
+        let barrier = level_barrier(
+            mip_levels - 1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        );
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            )
+        };
+    }
+
+    /// End recording a command buffer started with [`begin_one_time_commands`], submit it
+    /// and wait for its completion. The command buffer is freed even if an error occurs.
+    fn execute_one_time_commands(
+        device: &Device,
+        queue: vk::Queue,
+        pool: vk::CommandPool,
+        command_buffer: vk::CommandBuffer,
+    ) -> RendererResult<()> {
+        let command_buffers = [command_buffer];
 
-        // End recording
-        unsafe { device.end_command_buffer(command_buffer)? };
+        let result = (|| {
+            // End recording
+            unsafe { device.end_command_buffer(command_buffer)? };
 
-        // Submit and wait
-        {
+            // Submit and wait
             let submit_info = vk::SubmitInfo::default()
                 .command_buffers(&command_buffers)
                 ;
@@ -555,11 +1485,221 @@ mod texture {
                 device.queue_submit(queue, &submit_infos, vk::Fence::null())?;
                 device.queue_wait_idle(queue)?;
             };
-        }
+            Ok(())
+        })();
 
         // Free
         unsafe { device.free_command_buffers(pool, &command_buffers) };
 
-        Ok(executor_result)
+        result
+    }
+
+    /// Free a command buffer started with [`begin_one_time_commands`] without submitting it.
+    pub fn abort_one_time_commands(
+        device: &Device,
+        pool: vk::CommandPool,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        unsafe {
+            // The buffer is freed whether recording ends successfully or not.
+            let _ = device.end_command_buffer(command_buffer);
+            device.free_command_buffers(pool, &[command_buffer]);
+        }
+    }
+
+    /// End recording a command buffer started with [`begin_one_time_commands`] and submit it
+    /// without waiting for its completion.
+    ///
+    /// `signal` is signaled when the commands complete. A fence signaled at the same time is
+    /// returned. The command buffer must be freed and the fence destroyed once the fence is
+    /// signaled. The command buffer is freed if an error occurs.
+    pub fn submit_one_time_commands(
+        device: &Device,
+        queue: vk::Queue,
+        pool: vk::CommandPool,
+        command_buffer: vk::CommandBuffer,
+        signal: UploadSignal,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> RendererResult<vk::Fence> {
+        let command_buffers = [command_buffer];
+
+        let free = || unsafe { device.free_command_buffers(pool, &command_buffers) };
+        if let Err(error) = unsafe { device.end_command_buffer(command_buffer) } {
+            free();
+            return Err(error.into());
+        }
+
+        // Submit with a fence to know when the command buffer can be freed
+        let fence =
+            match unsafe { device.create_fence(&vk::FenceCreateInfo::default(), allocation_callbacks) } {
+                Ok(fence) => fence,
+                Err(error) => {
+                    free();
+                    return Err(error.into());
+                }
+            };
+        let (semaphore, value) = match signal {
+            UploadSignal::Semaphore(semaphore) => (semaphore, None),
+            UploadSignal::Timeline(semaphore, value) => (semaphore, Some(value)),
+        };
+        let signal_semaphores = [semaphore];
+        let signal_values = [value.unwrap_or_default()];
+        let mut timeline_info =
+            vk::TimelineSemaphoreSubmitInfo::default().signal_semaphore_values(&signal_values);
+        let mut submit_info = vk::SubmitInfo::default()
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+        if value.is_some() {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+        if let Err(error) = unsafe { device.queue_submit(queue, &[submit_info], fence) } {
+            unsafe { device.destroy_fence(fence, allocation_callbacks) };
+            free();
+            return Err(error.into());
+        }
+
+        Ok(fence)
+    }
+
+    /// Allocate a command buffer from `pool` and begin recording commands submitted once.
+    pub fn begin_one_time_commands(
+        device: &Device,
+        pool: vk::CommandPool,
+    ) -> RendererResult<vk::CommandBuffer> {
+        let command_buffer = {
+            let alloc_info = vk::CommandBufferAllocateInfo::default()
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_pool(pool)
+                .command_buffer_count(1);
+
+            unsafe { device.allocate_command_buffers(&alloc_info)?[0] }
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe { device.begin_command_buffer(command_buffer, &begin_info)? };
+
+        Ok(command_buffer)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn is_bad_data(result: RendererResult<Vec<usize>>) -> bool {
+            matches!(result, Err(RendererError::BadTextureData(_)))
+        }
+
+        #[test]
+        fn region_size_rounds_to_blocks() {
+            let rgba = FormatBlock::of(vk::Format::R8G8B8A8_UNORM).unwrap();
+            assert_eq!(rgba.region_size(3, 5), Some(60));
+
+            let bc1 = FormatBlock::of(vk::Format::BC1_RGBA_UNORM_BLOCK).unwrap();
+            assert_eq!(bc1.region_size(4, 4), Some(8));
+            assert_eq!(bc1.region_size(5, 1), Some(16));
+            assert_eq!(bc1.region_size(1, 1), Some(8));
+
+            let astc = FormatBlock::of(vk::Format::ASTC_10X6_UNORM_BLOCK).unwrap();
+            assert_eq!(astc.region_size(11, 6), Some(32));
+
+            let rgba32 = FormatBlock::of(vk::Format::R32G32B32A32_SFLOAT).unwrap();
+            assert_eq!(rgba32.region_size(u32::MAX, u32::MAX), None);
+        }
+
+        #[test]
+        fn check_data_size_of_mip_chain() {
+            // Levels of 8x8, 4x4, 2x2 and 1x1 texels each hold at least one block.
+            let offsets =
+                check_data_size(vk::Format::BC3_UNORM_BLOCK, 8, 8, 4, &[0; 112]).unwrap();
+            assert_eq!(offsets, [0, 64, 80, 96]);
+
+            let offsets = check_data_size(vk::Format::R8_UNORM, 4, 2, 3, &[0; 11]).unwrap();
+            assert_eq!(offsets, [0, 8, 10]);
+        }
+
+        #[test]
+        fn check_data_size_rejects_wrong_sizes() {
+            let format = vk::Format::BC1_RGBA_UNORM_BLOCK;
+            assert!(is_bad_data(check_data_size(format, 5, 5, 1, &[0; 16])));
+            assert!(is_bad_data(check_data_size(format, 5, 5, 1, &[0; 33])));
+            assert!(check_data_size(format, 5, 5, 1, &[0; 32]).is_ok());
+
+            assert!(is_bad_data(check_data_size(format, 4, 4, 0, &[])));
+            assert!(is_bad_data(check_data_size(format, 0, 4, 1, &[])));
+            assert!(is_bad_data(check_data_size(format, 4, 0, 1, &[])));
+            assert!(is_bad_data(check_data_size(format, 4, 4, 4, &[0; 24])));
+            assert!(is_bad_data(check_data_size(
+                vk::Format::R32G32B32A32_SFLOAT,
+                u32::MAX,
+                u32::MAX,
+                1,
+                &[]
+            )));
+            assert!(matches!(
+                check_data_size(vk::Format::D32_SFLOAT, 4, 4, 1, &[0; 64]),
+                Err(RendererError::UnsupportedFormat(_))
+            ));
+        }
+
+        #[test]
+        fn staging_levels_are_aligned_for_transfer_queues() {
+            let (width, height) = (7, 5);
+            let mip_levels = full_mip_levels(width, height);
+            let len = (0..mip_levels)
+                .map(|level| {
+                    let (level_width, level_height) = mip_level_extent(width, height, level);
+                    (level_width * level_height) as usize
+                })
+                .sum();
+            let data = (0..len).map(|byte| byte as u8).collect::<Vec<_>>();
+            let level_offsets =
+                check_data_size(vk::Format::R8_UNORM, width, height, mip_levels, &data).unwrap();
+            assert_eq!(level_offsets, [0, 35, 41]);
+
+            let (slices, staging_offsets) = staging_levels(&data, &level_offsets);
+            let staging = slices.concat();
+            for (level, (&offset, &staging_offset)) in
+                level_offsets.iter().zip(&staging_offsets).enumerate()
+            {
+                let end = level_offsets.get(level + 1).copied().unwrap_or(data.len());
+                let level_len = end - offset;
+                assert_eq!(
+                    staging[staging_offset..staging_offset + level_len],
+                    data[offset..end]
+                );
+            }
+
+            let copies = level_copies(width, height, 16, &staging_offsets);
+            assert_eq!(copies.len(), mip_levels as usize);
+            for copy in &copies {
+                assert_eq!(copy.buffer_offset % 4, 0);
+            }
+            assert_eq!(
+                (copies[2].image_extent.width, copies[2].image_extent.height),
+                (1, 1)
+            );
+        }
+
+        #[test]
+        fn check_region_rejects_empty_regions() {
+            let format = vk::Format::R8G8B8A8_UNORM;
+            assert!(check_region(format, 8, 8, [2, 2], [4, 4], &[0; 64]).is_ok());
+            assert!(matches!(
+                check_region(format, 8, 8, [2, 2], [0, 4], &[]),
+                Err(RendererError::BadTextureData(_))
+            ));
+            assert!(matches!(
+                check_region(format, 8, 8, [8, 8], [4, 0], &[]),
+                Err(RendererError::BadTextureData(_))
+            ));
+        }
+
+        #[test]
+        fn check_upload_generates_from_single_level() {
+            let format = vk::Format::R8G8B8A8_UNORM;
+            assert!(check_upload(format, 2, 2, 1, true, &[0; 16]).is_ok());
+            assert!(is_bad_data(check_upload(format, 2, 2, 2, true, &[0; 20])));
+        }
     }
 }